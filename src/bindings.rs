@@ -0,0 +1,374 @@
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use winit::{VirtualKeyCode as Key, ModifiersState};
+use siege_plugin_avatar_simple::MoveDirection;
+use errors::*;
+use state::State;
+use command::{CommandRegistry, Command, ArgSpec, ArgKind, Args};
+
+/// Something a key can be bound to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Action {
+    Forward,
+    Backward,
+    TurnLeft,
+    TurnRight,
+    StrafeLeft,
+    StrafeRight,
+    PitchUp,
+    PitchDown,
+    /// Start typing a chat line
+    Chat,
+    /// Start typing a command
+    Command,
+    Quit,
+    /// Turn mouse-look on or off
+    MouseLook,
+    /// Switch between first and third person
+    ToggleCamera,
+    /// Detach the camera from the avatar to fly it freely, or reattach it
+    FreeCamera,
+    /// Run a console command (without the leading slash)
+    Run(String),
+}
+
+pub const ACTION_NAMES: &'static [&'static str] = &[
+    "forward", "backward", "turn_left", "turn_right", "strafe_left", "strafe_right",
+    "pitch_up", "pitch_down", "chat", "command", "quit", "mouse_look", "toggle_camera",
+    "free_camera",
+];
+
+impl Action {
+    /// Parse an action name, or a command starting with '/'
+    pub fn from_name(name: &str) -> Option<Action> {
+        let name = name.trim();
+        if name.starts_with('/') && name.len() > 1 {
+            return Some(Action::Run(name[1..].to_owned()));
+        }
+        match name {
+            "forward" => Some(Action::Forward),
+            "backward" => Some(Action::Backward),
+            "turn_left" => Some(Action::TurnLeft),
+            "turn_right" => Some(Action::TurnRight),
+            "strafe_left" => Some(Action::StrafeLeft),
+            "strafe_right" => Some(Action::StrafeRight),
+            "pitch_up" => Some(Action::PitchUp),
+            "pitch_down" => Some(Action::PitchDown),
+            "chat" => Some(Action::Chat),
+            "command" => Some(Action::Command),
+            "quit" => Some(Action::Quit),
+            "mouse_look" => Some(Action::MouseLook),
+            "toggle_camera" => Some(Action::ToggleCamera),
+            "free_camera" => Some(Action::FreeCamera),
+            _ => None,
+        }
+    }
+
+    /// Whether the action is done again as its key repeats. Only /adjust is,
+    /// since it steps a value; other commands (e.g. toggles) would misfire.
+    pub fn repeats(&self) -> bool {
+        match *self {
+            Action::Run(ref command) => command.split_whitespace().next() == Some("adjust"),
+            _ => false,
+        }
+    }
+
+    /// The direction this action moves the avatar in, if it is a movement
+    pub fn move_direction(&self) -> Option<MoveDirection> {
+        match *self {
+            Action::Forward => Some(MoveDirection::Forward),
+            Action::Backward => Some(MoveDirection::Backward),
+            Action::TurnLeft => Some(MoveDirection::YawLeft),
+            Action::TurnRight => Some(MoveDirection::YawRight),
+            Action::StrafeLeft => Some(MoveDirection::StrafeLeft),
+            Action::StrafeRight => Some(MoveDirection::StrafeRight),
+            Action::PitchUp => Some(MoveDirection::PitchUp),
+            Action::PitchDown => Some(MoveDirection::PitchDown),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Action::Forward => "forward",
+            Action::Backward => "backward",
+            Action::TurnLeft => "turn_left",
+            Action::TurnRight => "turn_right",
+            Action::StrafeLeft => "strafe_left",
+            Action::StrafeRight => "strafe_right",
+            Action::PitchUp => "pitch_up",
+            Action::PitchDown => "pitch_down",
+            Action::Chat => "chat",
+            Action::Command => "command",
+            Action::Quit => "quit",
+            Action::MouseLook => "mouse_look",
+            Action::ToggleCamera => "toggle_camera",
+            Action::FreeCamera => "free_camera",
+            Action::Run(ref command) => return write!(f, "/{}", command),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Keys that can be bound, by name. Other keys are left alone.
+const KEY_NAMES: &'static [(&'static str, Key)] = &[
+    ("A", Key::A), ("B", Key::B), ("C", Key::C), ("D", Key::D), ("E", Key::E),
+    ("F", Key::F), ("G", Key::G), ("H", Key::H), ("I", Key::I), ("J", Key::J),
+    ("K", Key::K), ("L", Key::L), ("M", Key::M), ("N", Key::N), ("O", Key::O),
+    ("P", Key::P), ("Q", Key::Q), ("R", Key::R), ("S", Key::S), ("T", Key::T),
+    ("U", Key::U), ("V", Key::V), ("W", Key::W), ("X", Key::X), ("Y", Key::Y),
+    ("Z", Key::Z),
+    ("1", Key::Key1), ("2", Key::Key2), ("3", Key::Key3), ("4", Key::Key4),
+    ("5", Key::Key5), ("6", Key::Key6), ("7", Key::Key7), ("8", Key::Key8),
+    ("9", Key::Key9), ("0", Key::Key0),
+    ("F1", Key::F1), ("F2", Key::F2), ("F3", Key::F3), ("F4", Key::F4),
+    ("F5", Key::F5), ("F6", Key::F6), ("F7", Key::F7), ("F8", Key::F8),
+    ("F9", Key::F9), ("F10", Key::F10), ("F11", Key::F11), ("F12", Key::F12),
+    ("Up", Key::Up), ("Down", Key::Down), ("Left", Key::Left), ("Right", Key::Right),
+    ("PageUp", Key::PageUp), ("PageDown", Key::PageDown),
+    ("Home", Key::Home), ("End", Key::End),
+    ("Insert", Key::Insert), ("Delete", Key::Delete),
+    ("Escape", Key::Escape), ("Space", Key::Space), ("Return", Key::Return),
+    ("Back", Key::Back), ("Tab", Key::Tab),
+    ("Grave", Key::Grave), ("Minus", Key::Minus), ("Equals", Key::Equals),
+    ("LBracket", Key::LBracket), ("RBracket", Key::RBracket),
+    ("Backslash", Key::Backslash), ("Semicolon", Key::Semicolon),
+    ("Apostrophe", Key::Apostrophe), ("Comma", Key::Comma),
+    ("Period", Key::Period), ("Slash", Key::Slash),
+    ("Numpad0", Key::Numpad0), ("Numpad1", Key::Numpad1), ("Numpad2", Key::Numpad2),
+    ("Numpad3", Key::Numpad3), ("Numpad4", Key::Numpad4), ("Numpad5", Key::Numpad5),
+    ("Numpad6", Key::Numpad6), ("Numpad7", Key::Numpad7), ("Numpad8", Key::Numpad8),
+    ("Numpad9", Key::Numpad9),
+    ("Add", Key::Add), ("Subtract", Key::Subtract),
+    ("Multiply", Key::Multiply), ("Divide", Key::Divide),
+];
+
+/// The character a key types (without shift), so that the key which opens chat
+/// does not also end up in the line being typed
+pub fn key_char(key: Key) -> Option<char> {
+    match key {
+        Key::Grave => Some('`'),
+        Key::Slash => Some('/'),
+        Key::Minus => Some('-'),
+        Key::Equals => Some('='),
+        Key::LBracket => Some('['),
+        Key::RBracket => Some(']'),
+        Key::Backslash => Some('\\'),
+        Key::Semicolon => Some(';'),
+        Key::Apostrophe => Some('\''),
+        Key::Comma => Some(','),
+        Key::Period => Some('.'),
+        Key::Space => Some(' '),
+        _ => {
+            let name = key_name(key)?;
+            if name.len() == 1 {
+                name.chars().next().map(|c| c.to_ascii_lowercase())
+            } else {
+                None
+            }
+        }
+    }
+}
+
+fn key_name(key: Key) -> Option<&'static str> {
+    KEY_NAMES.iter().find(|&&(_, k)| k == key).map(|&(n, _)| n)
+}
+
+/// A key, with the modifiers that must be held along with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    pub key: Key,
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub logo: bool,
+}
+
+impl KeyChord {
+    pub fn new(key: Key, modifiers: ModifiersState) -> KeyChord {
+        KeyChord {
+            key: key,
+            shift: modifiers.shift,
+            ctrl: modifiers.ctrl,
+            alt: modifiers.alt,
+            logo: modifiers.logo,
+        }
+    }
+
+    /// Parse a chord such as "W", "ctrl+F3" or "logo+Escape" (case does not matter)
+    pub fn parse(text: &str) -> Result<KeyChord> {
+        let bad = || -> Error {
+            ErrorKind::Command(format!("Not a key: {}", text)).into()
+        };
+
+        let mut parts: Vec<&str> = text.trim().split('+').collect();
+        let key_part = parts.pop().ok_or_else(&bad)?;
+        let key = KEY_NAMES.iter()
+            .find(|&&(n, _)| n.eq_ignore_ascii_case(key_part))
+            .map(|&(_, k)| k)
+            .ok_or_else(&bad)?;
+
+        let mut chord = KeyChord { key: key, shift: false, ctrl: false, alt: false, logo: false };
+        for modifier in parts {
+            match &*modifier.to_lowercase() {
+                "shift" => chord.shift = true,
+                "ctrl" => chord.ctrl = true,
+                "alt" => chord.alt = true,
+                "logo" | "win" | "super" => chord.logo = true,
+                _ => return Err(bad()),
+            }
+        }
+        Ok(chord)
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ctrl { write!(f, "ctrl+")?; }
+        if self.alt { write!(f, "alt+")?; }
+        if self.logo { write!(f, "logo+")?; }
+        if self.shift { write!(f, "shift+")?; }
+        write!(f, "{}", key_name(self.key).unwrap_or("?"))
+    }
+}
+
+const DEFAULT_BINDINGS: &'static [(&'static str, &'static str)] = &[
+    ("W", "forward"), ("Up", "forward"),
+    ("S", "backward"), ("Down", "backward"),
+    ("A", "turn_left"), ("Left", "turn_left"),
+    ("D", "turn_right"), ("Right", "turn_right"),
+    ("Q", "strafe_left"),
+    ("E", "strafe_right"),
+    ("PageUp", "pitch_up"),
+    ("PageDown", "pitch_down"),
+    ("Grave", "chat"),
+    ("Slash", "command"),
+    ("logo+Escape", "quit"),
+    ("F2", "mouse_look"),
+    ("F3", "/adjust fov 0.5"),
+    ("F4", "/adjust fov -0.5"),
+    ("F5", "toggle_camera"),
+    ("F6", "free_camera"),
+    ("shift+F6", "/freeze_culling"),
+    ("Equals", "/adjust free_speed 5"),
+    ("Minus", "/adjust free_speed -5"),
+    ("F7", "/adjust blur_level -0.01"),
+    ("F8", "/adjust blur_level 0.01"),
+    ("F9", "/adjust bloom_strength -0.01"),
+    ("F10", "/adjust bloom_strength 0.01"),
+    ("F11", "/adjust bloom_cliff -0.01"),
+    ("F12", "/adjust bloom_cliff 0.01"),
+];
+
+/// The table of key bindings
+pub struct Bindings {
+    map: HashMap<KeyChord, Action>,
+}
+
+impl Bindings {
+    /// The default bindings, changed by those in the config file. In the
+    /// config, binding a key to "" unbinds it.
+    pub fn new(config: &BTreeMap<String, String>) -> Bindings {
+        let mut bindings = Bindings { map: HashMap::new() };
+        for &(chord, action) in DEFAULT_BINDINGS {
+            if let Err(e) = bindings.bind_names(chord, action) {
+                warn!("Bad default binding {} = {}: {}", chord, action, e);
+            }
+        }
+        for (chord, action) in config {
+            let result = if action.trim().is_empty() {
+                KeyChord::parse(chord).map(|c| { bindings.unbind(&c); })
+            } else {
+                bindings.bind_names(chord, action)
+            };
+            if let Err(e) = result {
+                warn!("Config binding {} = {}: {}", chord, action, e);
+            }
+        }
+        bindings
+    }
+
+    pub fn bind(&mut self, chord: KeyChord, action: Action) {
+        self.map.insert(chord, action);
+    }
+
+    fn bind_names(&mut self, chord: &str, action: &str) -> Result<()> {
+        let chord = KeyChord::parse(chord)?;
+        let action = Action::from_name(action).ok_or_else(|| -> Error {
+            ErrorKind::Command(format!("Not an action: {}", action)).into()
+        })?;
+        self.bind(chord, action);
+        Ok(())
+    }
+
+    /// Returns the action that was bound, if any
+    pub fn unbind(&mut self, chord: &KeyChord) -> Option<Action> {
+        self.map.remove(chord)
+    }
+
+    /// Find the action for a key press. A binding without shift also matches
+    /// when shift is held, unless shift+key is bound to something else.
+    pub fn lookup(&self, chord: &KeyChord) -> Option<&Action> {
+        self.map.get(chord).or_else(|| {
+            if chord.shift {
+                self.map.get(&KeyChord { shift: false, ..*chord })
+            } else {
+                None
+            }
+        })
+    }
+
+    /// All bindings, sorted by key
+    pub fn list(&self) -> Vec<(KeyChord, Action)> {
+        let mut list: Vec<(KeyChord, Action)> = self.map.iter()
+            .map(|(c, a)| (*c, a.clone()))
+            .collect();
+        list.sort_by_key(|&(c, _)| c.to_string());
+        list
+    }
+}
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(Command::new(
+        "bind", vec![ArgSpec::required("key", ArgKind::Word),
+                     ArgSpec::required("action", ArgKind::Rest)],
+        "Bind a key (e.g. ctrl+F5) to an action, or to a /command", bind));
+    registry.register(Command::new(
+        "unbind", vec![ArgSpec::required("key", ArgKind::Word)],
+        "Remove a key binding", unbind));
+    registry.register(Command::new(
+        "bindings", vec![], "List key bindings", bindings));
+}
+
+fn bind(state: &State, args: &Args) -> Result<Option<String>> {
+    let chord = KeyChord::parse(args.word(0))?;
+    let action = match Action::from_name(args.word(1)) {
+        Some(action) => action,
+        None => {
+            return Err(ErrorKind::Command(
+                format!("Not an action: {} (use /command or one of: {})",
+                        args.word(1), ACTION_NAMES.join(", "))).into());
+        }
+    };
+    let output = format!("{} = {}", chord, action);
+    state.bindings.write().unwrap().bind(chord, action);
+    Ok(Some(output))
+}
+
+fn unbind(state: &State, args: &Args) -> Result<Option<String>> {
+    let chord = KeyChord::parse(args.word(0))?;
+    Ok(Some(match state.bindings.write().unwrap().unbind(&chord) {
+        Some(action) => format!("{} is no longer bound to {}", chord, action),
+        None => format!("{} was not bound", chord),
+    }))
+}
+
+fn bindings(state: &State, _args: &Args) -> Result<Option<String>> {
+    let lines: Vec<String> = state.bindings.read().unwrap().list().iter()
+        .map(|&(ref c, ref a)| format!("{} = {}", c, a))
+        .collect();
+    Ok(Some(lines.join("\n")))
+}
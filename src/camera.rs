@@ -1,8 +1,13 @@
 
 use dacite::core::Extent2D;
-use siege_math::{Angle, Point3, Mat3, Mat4, Vec4, NQuat, Y_AXIS_F32, X_AXIS_F32};
+use siege_math::{Angle, Point3, Mat3, Mat4, Vec3, Vec4, NQuat, Y_AXIS_F32, X_AXIS_F32};
 use std::f32::consts::PI;
-use siege_plugin_avatar_simple::Placement;
+use std::time::Instant;
+use siege_plugin_avatar_simple::{Placement, MoveDirection};
+use terrain::Terrain;
+use state::State;
+use command::{CommandRegistry, Command, ArgSpec, ArgKind, Args};
+use errors::*;
 use Config;
 
 // Field of view depends how far back the viewers head is from the monitor, and
@@ -10,9 +15,9 @@ use Config;
 // use a more narrow one. The user should be able to adjust this.
 // For a desktop monitor, the FOV should be around 60 degrees.
 // Oculus rift goes to 110, targetting 140 for upcoming models.
-const MAX_FOV: f32 = 110.0 * PI / 180.0;    // 110 degrees
-const DEFAULT_FOV: f32 = 60.0 * PI / 180.0; // 60 degrees
-const MIN_FOV: f32 = 45.0 * PI / 180.0;     // 45 degrees
+pub const MAX_FOV: f32 = 110.0 * PI / 180.0;    // 110 degrees
+pub const DEFAULT_FOV: f32 = 60.0 * PI / 180.0; // 60 degrees
+pub const MIN_FOV: f32 = 45.0 * PI / 180.0;     // 45 degrees
 
 // we measure in meters, so this goes from 25 centimeters to the far plane.
 pub const NEAR_PLANE: f32 = 0.25;
@@ -20,8 +25,308 @@ pub const NEAR_PLANE: f32 = 0.25;
 // 100 kilometers
 pub const FAR_PLANE: f32 = 100_000.0;
 
+// How far behind the avatar the third-person camera sits, in meters
+pub const MIN_ORBIT_DISTANCE: f32 = 1.0;
+pub const DEFAULT_ORBIT_DISTANCE: f32 = 6.0;
+pub const MAX_ORBIT_DISTANCE: f32 = 50.0;
+
+// The third-person camera does not look straight up or down either
+const MAX_ORBIT_PITCH: f32 = 85.0 * PI / 180.0;
+
+// How far apart we check the terrain along the boom, in meters
+const BOOM_STEP: f32 = 0.25;
+
+// How long the camera takes to catch up to where it should be when smoothing,
+// in seconds (this is the time constant, zero is no smoothing)
+pub const MAX_SMOOTHING: f32 = 2.0;
+pub const DEFAULT_POSITION_SMOOTHING: f32 = 0.15;
+pub const DEFAULT_ORIENTATION_SMOOTHING: f32 = 0.08;
+
+// Smoothing jumps rather than eases over distances this far (e.g. teleports)
+const SMOOTHING_SNAP_DISTANCE: f32 = 25.0;
+
+// The smoothed camera is kept at least this far above the ground
+const SMOOTHING_CLEARANCE: f32 = 0.5;
+
+// How fast the free camera flies, in meters per second
+pub const MIN_FREE_SPEED: f32 = 0.5;
+pub const DEFAULT_FREE_SPEED: f32 = 10.0;
+pub const MAX_FREE_SPEED: f32 = 1000.0;
+
+// How fast the free camera turns with the keys, in radians per second
+const FREE_TURN_RATE: f32 = 90.0 * PI / 180.0;
+
+/// Where the camera is, relative to the avatar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    /// At the avatar's eyes
+    FirstPerson,
+    /// On a boom behind the avatar, looking at it
+    ThirdPerson,
+}
+
+// Indices into FreeCamera::held
+const FREE_FORWARD: usize = 0;
+const FREE_BACKWARD: usize = 1;
+const FREE_LEFT: usize = 2;
+const FREE_RIGHT: usize = 3;
+const FREE_TURN_LEFT: usize = 4;
+const FREE_TURN_RIGHT: usize = 5;
+const FREE_PITCH_UP: usize = 6;
+const FREE_PITCH_DOWN: usize = 7;
+
+/// A camera detached from the avatar, flying freely (for debugging)
+#[derive(Debug, Clone)]
+pub struct FreeCamera {
+    pub position: Vec3<f32>,
+    pub yaw: f32,
+    pub pitch: f32,
+    // Which movement keys are held
+    held: [bool; 8],
+    last_update: Instant,
+}
+
+impl FreeCamera {
+    pub fn new(placement: &Placement) -> FreeCamera {
+        FreeCamera {
+            position: placement.position.0,
+            yaw: placement.yaw,
+            pitch: placement.pitch,
+            held: [false; 8],
+            last_update: Instant::now(),
+        }
+    }
+
+    pub fn placement(&self) -> Placement {
+        Placement::new(Point3(self.position), self.pitch, self.yaw)
+    }
+
+    /// Start or stop moving in a direction (the same directions the avatar
+    /// moves in, except that forward follows the pitch)
+    pub fn move_cmd(&mut self, direction: MoveDirection, positive: bool) {
+        #[allow(unreachable_patterns)]
+        let index = match direction {
+            MoveDirection::Forward => FREE_FORWARD,
+            MoveDirection::Backward => FREE_BACKWARD,
+            MoveDirection::StrafeLeft => FREE_LEFT,
+            MoveDirection::StrafeRight => FREE_RIGHT,
+            MoveDirection::YawLeft => FREE_TURN_LEFT,
+            MoveDirection::YawRight => FREE_TURN_RIGHT,
+            MoveDirection::PitchUp => FREE_PITCH_UP,
+            MoveDirection::PitchDown => FREE_PITCH_DOWN,
+            _ => return,
+        };
+        self.held[index] = positive;
+    }
+
+    /// Turn by (yaw, pitch) radians
+    pub fn look(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = (self.yaw + yaw) % (2.0 * PI);
+        self.pitch = (self.pitch + pitch).max(-MAX_ORBIT_PITCH).min(MAX_ORBIT_PITCH);
+    }
+
+    /// Stay put for the time since the last update (while something else
+    /// moves the view), so that it is not flown through later
+    pub fn hold(&mut self, now: Instant) {
+        self.last_update = now;
+    }
+
+    /// Fly for the time since the last update
+    pub fn update(&mut self, now: Instant, speed: f32) {
+        let elapsed = now.duration_since(self.last_update);
+        self.last_update = now;
+        let dt = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9;
+
+        let axis = |positive: usize, negative: usize| -> f32 {
+            (self.held[positive] as i32 - self.held[negative] as i32) as f32
+        };
+        let turn = axis(FREE_TURN_LEFT, FREE_TURN_RIGHT) * FREE_TURN_RATE * dt;
+        let tilt = axis(FREE_PITCH_UP, FREE_PITCH_DOWN) * FREE_TURN_RATE * dt;
+        let ahead = axis(FREE_FORWARD, FREE_BACKWARD) * speed * dt;
+        let across = axis(FREE_RIGHT, FREE_LEFT) * speed * dt;
+        self.look(turn, tilt);
+
+        let forward = forward_vector(self.yaw, self.pitch);
+        let right = Vec3::new(self.yaw.cos(), 0.0, self.yaw.sin());
+        self.position = Vec3::new(
+            self.position.x + forward.x * ahead + right.x * across,
+            self.position.y + forward.y * ahead,
+            self.position.z + forward.z * ahead + right.z * across);
+    }
+}
+
+/// A value that follows a target like a critically damped spring: it eases
+/// in without overshooting
+#[derive(Debug, Clone, Copy)]
+struct Damped {
+    value: f32,
+    velocity: f32,
+}
+
+impl Damped {
+    fn new(value: f32) -> Damped {
+        Damped { value: value, velocity: 0.0 }
+    }
+
+    // Follow the target for dt seconds, with the given time constant
+    fn update(&mut self, target: f32, time: f32, dt: f32) -> f32 {
+        if time <= 0.0 {
+            *self = Damped::new(target);
+            return target;
+        }
+        // (the exponential is approximated, which is stable for any dt)
+        let omega = 2.0 / time;
+        let x = omega * dt;
+        let decay = 1.0 / (1.0 + x + 0.48 * x * x + 0.235 * x * x * x);
+        let change = self.value - target;
+        let temp = (self.velocity + omega * change) * dt;
+        self.velocity = (self.velocity - omega * temp) * decay;
+        self.value = target + (change + temp) * decay;
+        self.value
+    }
+}
+
+/// Where the smoothed camera is
+#[derive(Debug, Clone)]
+struct Smoothed {
+    x: Damped,
+    y: Damped,
+    z: Damped,
+    yaw: Damped,
+    pitch: Damped,
+    last_update: Instant,
+}
+
+impl Smoothed {
+    fn new(placement: &Placement, now: Instant) -> Smoothed {
+        let p = placement.position.0;
+        Smoothed {
+            x: Damped::new(p.x),
+            y: Damped::new(p.y),
+            z: Damped::new(p.z),
+            yaw: Damped::new(placement.yaw),
+            pitch: Damped::new(placement.pitch),
+            last_update: now,
+        }
+    }
+}
+
+/// The way a camera faces (y is down, and positive yaw turns left)
+fn forward_vector(yaw: f32, pitch: f32) -> Vec3<f32> {
+    Vec3::new(-yaw.sin() * pitch.cos(), -pitch.sin(), yaw.cos() * pitch.cos())
+}
+
+/// A copy of a placement (Placement is not Clone)
+pub fn copy_placement(placement: &Placement) -> Placement {
+    Placement::new(placement.position, placement.pitch, placement.yaw)
+}
+
+/// Where something is, relative to the view frustum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Containment {
+    Inside,
+    /// Partly inside (or close enough to the corners of the frustum that the
+    /// test cannot tell it is outside)
+    Intersecting,
+    Outside,
+}
+
+/// A half-line in world space, such as from the camera through the cursor
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    /// (unit length)
+    pub direction: Vec3<f32>,
+}
+
+impl Ray {
+    /// The point this far along the ray
+    pub fn at(&self, t: f32) -> Point3<f32> {
+        Point3(Vec3::new(self.origin.0.x + self.direction.x * t,
+                         self.origin.0.y + self.direction.y * t,
+                         self.origin.0.z + self.direction.z * t))
+    }
+
+    /// How far along the ray it first meets a sphere, if it does. A ray that
+    /// starts inside the sphere meets it at once.
+    pub fn hit_sphere(&self, center: &Point3<f32>, radius: f32) -> Option<f32> {
+        let to_center = Vec3::new(center.0.x - self.origin.0.x,
+                                  center.0.y - self.origin.0.y,
+                                  center.0.z - self.origin.0.z);
+        let along = to_center.dot(self.direction);
+        let miss_sq = to_center.dot(to_center) - along * along;
+        if miss_sq > radius * radius {
+            return None;
+        }
+        let half_chord = (radius * radius - miss_sq).sqrt();
+        if along + half_chord < 0.0 {
+            return None; // behind
+        }
+        Some((along - half_chord).max(0.0))
+    }
+
+    /// How far along the ray it first meets an oriented box, if it does. The
+    /// box is given as for Camera::obb_containment.
+    pub fn hit_cuboid(&self, center: &Point3<f32>, half_axes: &[Vec3<f32>; 3]) -> Option<f32> {
+        let to_center = Vec3::new(center.0.x - self.origin.0.x,
+                                  center.0.y - self.origin.0.y,
+                                  center.0.z - self.origin.0.z);
+        // Clip the ray between each pair of opposite faces (the slab method)
+        let (mut near, mut far) = (0.0_f32, ::std::f32::MAX);
+        for axis in half_axes.iter() {
+            let half_length = axis.dot(*axis).sqrt();
+            if half_length < 1e-6 {
+                continue;
+            }
+            let unit = *axis * (1.0 / half_length);
+            let e = unit.dot(to_center);
+            let f = unit.dot(self.direction);
+            if f.abs() < 1e-6 {
+                // Parallel to these faces, so it is between them or never
+                if e.abs() > half_length {
+                    return None;
+                }
+                continue;
+            }
+            let (t1, t2) = ((e - half_length) / f, (e + half_length) / f);
+            near = near.max(t1.min(t2));
+            far = far.min(t1.max(t2));
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+}
+
+/// Where something solid is in world space, for culling and picking
+#[derive(Debug, Clone, Copy)]
+pub enum Bounds {
+    Sphere(Point3<f32>, f32),
+    /// An oriented box, given as for Camera::obb_containment
+    Cuboid(Point3<f32>, [Vec3<f32>; 3]),
+}
+
+impl Bounds {
+    /// How far along a ray it first meets these bounds, if it does
+    pub fn ray_hit(&self, ray: &Ray) -> Option<f32> {
+        match *self {
+            Bounds::Sphere(ref center, radius) => ray.hit_sphere(center, radius),
+            Bounds::Cuboid(ref center, ref half_axes) => ray.hit_cuboid(center, half_axes),
+        }
+    }
+
+    /// Whether it might be in view of the camera
+    pub fn might_be_visible(&self, camera: &Camera) -> bool {
+        match *self {
+            Bounds::Sphere(ref center, radius) => camera.might_be_visible(center, radius),
+            Bounds::Cuboid(ref center, ref half_axes) =>
+                camera.obb_containment(center, half_axes) != Containment::Outside,
+        }
+    }
+}
+
 /// This represents the camera.
-/// Currently, the camera placement is the same as that of the avatar (1st person only)
 #[derive(Debug)]
 pub struct Camera {
     /// Field of view (Angle for zoom/wide, based on width)
@@ -30,10 +335,33 @@ pub struct Camera {
     pub extent: Extent2D,
     /// View matrix
     pub view_matrix: Mat4<f32>,
-    /// Camera model matrix
+    /// Camera model matrix (of the view being culled for, which is not the
+    /// view being shown when the culling is frozen)
     pub camera_model_matrix: Mat4<f32>,
+    /// Where the view is from (as of the last update)
+    pub view_position: Vec3<f32>,
+    pub view_yaw: f32,
+    pub view_pitch: f32,
     /// Frustum planes (for view frustum culling)
-    pub frustum_planes: [Vec4<f32>; 6]
+    pub frustum_planes: [Vec4<f32>; 6],
+    pub mode: CameraMode,
+    /// Length of the third-person boom, in meters
+    pub orbit_distance: f32,
+    /// Third-person angles around the avatar, relative to where it faces
+    /// (radians, positive is to the left and from above)
+    pub orbit_yaw: f32,
+    pub orbit_pitch: f32,
+    /// Smoothing time constants for the position and orientation of the
+    /// camera following the avatar, in seconds (zero is none)
+    pub position_smoothing: f32,
+    pub orientation_smoothing: f32,
+    smoothed: Option<Smoothed>,
+    /// The detached debug camera, while it is in use
+    pub free: Option<FreeCamera>,
+    /// Free camera speed, in meters per second
+    pub free_speed: f32,
+    /// While the free camera is in use, keep culling for the avatar's view
+    pub freeze_culling: bool,
 }
 
 impl Camera {
@@ -52,12 +380,25 @@ impl Camera {
             },
             view_matrix: view_matrix,
             camera_model_matrix: camera_model_matrix,
+            view_position: Vec3::new(0.0, 0.0, 0.0),
+            view_yaw: 0.0,
+            view_pitch: 0.0,
             frustum_planes: [Vec4::<f32>::new(0.0, 0.0, 1.0, 0.0),
                              Vec4::<f32>::new(0.0, 0.0, 1.0, 0.0),
                              Vec4::<f32>::new(0.0, 0.0, 1.0, 0.0),
                              Vec4::<f32>::new(0.0, 0.0, 1.0, 0.0),
                              Vec4::<f32>::new(0.0, 0.0, 1.0, 0.0),
                              Vec4::<f32>::new(0.0, 0.0, 1.0, 0.0)],
+            mode: CameraMode::FirstPerson,
+            orbit_distance: DEFAULT_ORBIT_DISTANCE,
+            orbit_yaw: 0.0,
+            orbit_pitch: 0.0,
+            position_smoothing: DEFAULT_POSITION_SMOOTHING,
+            orientation_smoothing: DEFAULT_ORIENTATION_SMOOTHING,
+            smoothed: None,
+            free: None,
+            free_speed: DEFAULT_FREE_SPEED,
+            freeze_culling: false,
         }
     }
 
@@ -65,18 +406,140 @@ impl Camera {
         self.extent.width as f32 / self.extent.height as f32
     }
 
-    pub fn update_placement(&mut self, placement: &Placement) {
+    /// View from one placement, and cull for another (usually the same one)
+    pub fn update_placement(&mut self, placement: &Placement, cull_placement: &Placement) {
         self.view_matrix = fps_view(&placement);
-        self.camera_model_matrix = camera_model_matrix(&placement);
+        self.view_position = placement.position.0;
+        self.view_yaw = placement.yaw;
+        self.view_pitch = placement.pitch;
+        self.camera_model_matrix = camera_model_matrix(&cull_placement);
         self.recompute_frustum_planes();
     }
 
+    /// Switch between first and third person
+    pub fn toggle_mode(&mut self) -> CameraMode {
+        self.mode = match self.mode {
+            CameraMode::FirstPerson => CameraMode::ThirdPerson,
+            CameraMode::ThirdPerson => CameraMode::FirstPerson,
+        };
+        self.mode
+    }
+
+    /// Detach the camera from the avatar, starting from where it is now, or
+    /// reattach it. Returns true if it is now detached.
+    pub fn toggle_free(&mut self, from: &Placement) -> bool {
+        self.free = match self.free {
+            Some(_) => None,
+            None => Some(FreeCamera::new(from)),
+        };
+        self.free.is_some()
+    }
+
+    /// Move the third-person camera in (positive) or out, by scroll-wheel lines
+    pub fn zoom_orbit(&mut self, lines: f32) {
+        self.orbit_distance = (self.orbit_distance * 0.9_f32.powf(lines))
+            .max(MIN_ORBIT_DISTANCE).min(MAX_ORBIT_DISTANCE);
+    }
+
+    /// Where to view from, given where the avatar is. In third person the
+    /// boom is shortened where it would go into the terrain. This ignores the
+    /// free camera.
+    pub fn follow_placement(&self, avatar: &Placement, terrain: &Terrain) -> Placement {
+        if self.mode == CameraMode::FirstPerson {
+            return Placement::new(avatar.position, avatar.pitch, avatar.yaw);
+        }
+
+        let yaw = avatar.yaw + self.orbit_yaw;
+        // (looking down on the avatar means pitching the camera down)
+        let pitch = (avatar.pitch - self.orbit_pitch)
+            .max(-MAX_ORBIT_PITCH).min(MAX_ORBIT_PITCH);
+
+        let forward = forward_vector(yaw, pitch);
+        let target = avatar.position.0;
+        let at = |distance: f32| -> Vec3<f32> {
+            Vec3::new(target.x - forward.x * distance,
+                      target.y - forward.y * distance,
+                      target.z - forward.z * distance)
+        };
+
+        // Walk out along the boom, stopping short of the terrain (get_y is at eye
+        // level above the ground, and smaller y is higher)
+        let mut distance = 0.0;
+        while distance < self.orbit_distance {
+            let next = (distance + BOOM_STEP).min(self.orbit_distance);
+            let p = at(next);
+            if p.y > terrain.get_y(p.x, p.z) {
+                break;
+            }
+            distance = next;
+        }
+
+        Placement::new(Point3(at(distance)), pitch, yaw)
+    }
+
+    /// Ease the camera toward where it should be, rather than snapping there.
+    /// First person is for precise aiming, so there only the height is eased
+    /// (steps in the terrain would otherwise jolt the view).
+    pub fn smooth(&mut self, target: Placement, terrain: &Terrain, now: Instant) -> Placement {
+        let (position_time, orientation_time) = (self.position_smoothing,
+                                                 self.orientation_smoothing);
+        let precise = self.mode == CameraMode::FirstPerson;
+
+        let t = target.position.0;
+        let jumped = match self.smoothed {
+            Some(ref s) => {
+                let (dx, dy, dz) = (t.x - s.x.value, t.y - s.y.value, t.z - s.z.value);
+                dx * dx + dy * dy + dz * dz > SMOOTHING_SNAP_DISTANCE * SMOOTHING_SNAP_DISTANCE
+            },
+            None => true,
+        };
+        if jumped {
+            self.smoothed = Some(Smoothed::new(&target, now));
+            return target;
+        }
+        let s = self.smoothed.as_mut().unwrap();
+
+        let elapsed = now.duration_since(s.last_update);
+        s.last_update = now;
+        let dt = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9;
+
+        let (across_time, angle_time) = if precise {
+            (0.0, 0.0)
+        } else {
+            (position_time, orientation_time)
+        };
+        let x = s.x.update(t.x, across_time, dt);
+        let z = s.z.update(t.z, across_time, dt);
+        let mut y = s.y.update(t.y, position_time, dt);
+
+        // Stay above the ground (smaller y is higher)
+        let lowest = terrain.ground_y(x, z) - SMOOTHING_CLEARANCE;
+        if y > lowest {
+            y = lowest;
+            s.y = Damped::new(y);
+        }
+
+        // Yaw wraps around, so go the short way
+        let mut yaw_delta = (target.yaw - s.yaw.value) % (2.0 * PI);
+        if yaw_delta > PI {
+            yaw_delta -= 2.0 * PI;
+        } else if yaw_delta < -PI {
+            yaw_delta += 2.0 * PI;
+        }
+        let yaw_target = s.yaw.value + yaw_delta;
+        let yaw = s.yaw.update(yaw_target, angle_time, dt);
+        let pitch = s.pitch.update(target.pitch, angle_time, dt);
+
+        Placement::new(Point3(Vec3::new(x, y, z)), pitch, yaw)
+    }
+
     pub fn fovy(&self) -> Angle<f32> {
         self.fovx / self.aspect_ratio()
     }
 
-    pub fn adjust_fovx(&mut self, angle: Angle<f32>) {
-        self.fovx = self.fovx + angle;
+    /// Set the horizontal field of view (clamped to a sensible range)
+    pub fn set_fovx(&mut self, angle: Angle<f32>) {
+        self.fovx = angle;
         if self.fovx.as_radians() > MAX_FOV {
             self.fovx = Angle::from_radians(MAX_FOV);
         }
@@ -132,6 +595,97 @@ impl Camera {
         }
         true
     }
+
+    /// Where a point in the world appears on the screen: (x, y) in pixels,
+    /// and the depth (how far in front of the camera it is, in meters). None
+    /// if it is in front of the near plane, or outside the view.
+    pub fn project(&self, point: &Point3<f32>) -> Option<(f32, f32, f32)> {
+        let eye = &self.view_matrix * &Vec4::new(point.0.x, point.0.y, point.0.z, 1.0);
+        let depth = eye.z;
+        if depth < NEAR_PLANE || depth > FAR_PLANE {
+            return None;
+        }
+        // (as perspective_matrix_fov_vulkan does it)
+        let d = 1.0 / (self.fovx.as_radians() / 2.0).tan();
+        let ndc_x = d * eye.x / depth;
+        let ndc_y = d * self.aspect_ratio() * eye.y / depth;
+        if ndc_x.abs() > 1.0 || ndc_y.abs() > 1.0 {
+            return None;
+        }
+        Some(((ndc_x + 1.0) / 2.0 * self.extent.width as f32,
+              (ndc_y + 1.0) / 2.0 * self.extent.height as f32,
+              depth))
+    }
+
+    /// The ray from the camera through a point on the screen, in pixels
+    pub fn screen_ray(&self, x: f32, y: f32) -> Ray {
+        // Undo the projection (see perspective_matrix_fov_vulkan), to get a
+        // direction in camera model space at z = 1
+        let d = 1.0 / (self.fovx.as_radians() / 2.0).tan();
+        let ndc_x = 2.0 * x / self.extent.width as f32 - 1.0;
+        let ndc_y = 2.0 * y / self.extent.height as f32 - 1.0;
+        let direction = Vec4::new(ndc_x / d, ndc_y / (d * self.aspect_ratio()), 1.0, 0.0);
+
+        // (camera_model_matrix is for culling, which may not be this view)
+        let view = Placement::new(Point3(self.view_position), self.view_pitch, self.view_yaw);
+        let direction = (&camera_model_matrix(&view) * &direction).truncate_w();
+        let length = direction.dot(direction).sqrt();
+        Ray {
+            origin: Point3(self.view_position),
+            direction: direction * (1.0 / length),
+        }
+    }
+
+    /// Test an axis-aligned box (in world space) against the view frustum.
+    /// For each plane, if the corner furthest along the plane normal (the
+    /// positive vertex) is outside, the box is outside; else if the nearest
+    /// corner (the negative vertex) is outside, the box crosses the plane.
+    pub fn aabb_containment(&self, min: &Point3<f32>, max: &Point3<f32>) -> Containment {
+        let mut result = Containment::Inside;
+        for plane in self.frustum_planes.iter() {
+            let normal = plane.truncate_w();
+            let pick = |n: f32, toward: f32, away: f32| if n >= 0.0 { toward } else { away };
+            let positive = Vec3::new(pick(normal.x, max.0.x, min.0.x),
+                                     pick(normal.y, max.0.y, min.0.y),
+                                     pick(normal.z, max.0.z, min.0.z));
+            let negative = Vec3::new(pick(normal.x, min.0.x, max.0.x),
+                                     pick(normal.y, min.0.y, max.0.y),
+                                     pick(normal.z, min.0.z, max.0.z));
+            if normal.dot(positive) + plane.w < 0.0 {
+                return Containment::Outside;
+            }
+            if normal.dot(negative) + plane.w < 0.0 {
+                result = Containment::Intersecting;
+            }
+        }
+        result
+    }
+
+    /// Test an oriented box (in world space) against the view frustum. The
+    /// box is given by its center and its three half axes (its edge
+    /// directions, each scaled to half the length of the edge). This is the
+    /// same test as for an axis-aligned box: the positive and negative
+    /// vertices are the center plus and minus how far the box reaches along
+    /// the plane normal.
+    pub fn obb_containment(&self, center: &Point3<f32>, half_axes: &[Vec3<f32>; 3])
+                           -> Containment
+    {
+        let mut result = Containment::Inside;
+        for plane in self.frustum_planes.iter() {
+            let normal = plane.truncate_w();
+            let distance = normal.dot(center.0) + plane.w;
+            let reach = normal.dot(half_axes[0]).abs()
+                + normal.dot(half_axes[1]).abs()
+                + normal.dot(half_axes[2]).abs();
+            if distance + reach < 0.0 {
+                return Containment::Outside;
+            }
+            if distance - reach < 0.0 {
+                result = Containment::Intersecting;
+            }
+        }
+        result
+    }
 }
 
 fn fps_view(placement: &Placement) -> Mat4<f32>
@@ -190,3 +744,158 @@ fn camera_model_matrix(placement: &Placement) -> Mat4<f32>
     m.set_translation(placement.position);
     m
 }
+
+const ON_OFF: &'static [&'static str] = &["on", "off"];
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(Command::new(
+        "freeze_culling", vec![ArgSpec::optional("state", ArgKind::Choice(ON_OFF))],
+        "Keep culling for the avatar's view while the free camera flies (toggles if no state)",
+        freeze_culling));
+}
+
+fn freeze_culling(state: &State, args: &Args) -> Result<Option<String>> {
+    let mut camera = state.camera.write().unwrap();
+    camera.freeze_culling = if args.len() > 0 {
+        args.word(0) == "on"
+    } else {
+        !camera.freeze_culling
+    };
+    Ok(Some(if camera.freeze_culling {
+        "Culling is frozen at the avatar's view".to_owned()
+    } else {
+        "Culling follows the camera".to_owned()
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Camera, Containment};
+    use siege_math::{Point3, Vec3};
+    use siege_plugin_avatar_simple::Placement;
+    use Config;
+
+    // At the origin, looking down +Z with a 60 degree (horizontal) field of view
+    fn camera() -> Camera {
+        let mut camera = Camera::new(&Config::default());
+        let placement = Placement::new(Point3::new(0.0, 0.0, 0.0), 0.0, 0.0);
+        camera.update_placement(&placement, &placement);
+        camera
+    }
+
+    fn aabb(camera: &Camera, min: (f32, f32, f32), max: (f32, f32, f32)) -> Containment {
+        camera.aabb_containment(&Point3::new(min.0, min.1, min.2),
+                                &Point3::new(max.0, max.1, max.2))
+    }
+
+    #[test]
+    fn test_aabb_containment() {
+        let camera = camera();
+        assert_eq!(aabb(&camera, (-1.0, -1.0, 9.0), (1.0, 1.0, 11.0)), Containment::Inside);
+        assert_eq!(aabb(&camera, (-1.0, -1.0, -11.0), (1.0, 1.0, -9.0)), Containment::Outside);
+        assert_eq!(aabb(&camera, (99.0, -1.0, 9.0), (101.0, 1.0, 11.0)), Containment::Outside);
+        assert_eq!(aabb(&camera, (-1.0, 49.0, 9.0), (1.0, 51.0, 11.0)), Containment::Outside);
+        // across the near plane
+        assert_eq!(aabb(&camera, (-1.0, -1.0, -1.0), (1.0, 1.0, 1.0)), Containment::Intersecting);
+        // across the right edge
+        assert_eq!(aabb(&camera, (4.0, -1.0, 9.0), (8.0, 1.0, 11.0)), Containment::Intersecting);
+        // beyond the far plane
+        assert_eq!(aabb(&camera, (-1.0, -1.0, 100_001.0), (1.0, 1.0, 100_002.0)),
+                   Containment::Outside);
+    }
+
+    #[test]
+    fn test_aabb_spanning_frustum() {
+        // A wall across the whole view has every corner outside the frustum,
+        // but is in view
+        let camera = camera();
+        let (min, max) = ((-1000.0, -1000.0, 9.0), (1000.0, 1000.0, 11.0));
+        for &x in &[min.0, max.0] {
+            for &y in &[min.1, max.1] {
+                for &z in &[min.2, max.2] {
+                    assert!(!camera.might_be_visible(&Point3::new(x, y, z), 0.0));
+                }
+            }
+        }
+        assert_eq!(aabb(&camera, min, max), Containment::Intersecting);
+    }
+
+    #[test]
+    fn test_screen_ray() {
+        let camera = camera();
+        let (width, height) = (camera.extent.width as f32, camera.extent.height as f32);
+
+        // The middle of the screen is straight ahead
+        let ray = camera.screen_ray(width / 2.0, height / 2.0);
+        assert!((ray.direction.z - 1.0).abs() < 1e-5);
+
+        // The right edge is half the field of view to the right
+        let ray = camera.screen_ray(width, height / 2.0);
+        let angle = ray.direction.x.atan2(ray.direction.z);
+        assert!((angle - camera.fovx.as_radians() / 2.0).abs() < 1e-4);
+
+        // Lower on the screen is down, which is +y
+        assert!(camera.screen_ray(width / 2.0, height).direction.y > 0.0);
+    }
+
+    #[test]
+    fn test_project() {
+        let camera = camera();
+        let (width, height) = (camera.extent.width as f32, camera.extent.height as f32);
+
+        let (x, y, depth) = camera.project(&Point3::new(0.0, 0.0, 10.0)).unwrap();
+        assert!((x - width / 2.0).abs() < 1e-3 && (y - height / 2.0).abs() < 1e-3);
+        assert!((depth - 10.0).abs() < 1e-4);
+
+        // Behind, too close, and off to the side
+        assert!(camera.project(&Point3::new(0.0, 0.0, -10.0)).is_none());
+        assert!(camera.project(&Point3::new(0.0, 0.0, 0.1)).is_none());
+        assert!(camera.project(&Point3::new(100.0, 0.0, 10.0)).is_none());
+
+        // Back along a picking ray, to the same pixel
+        let ray = camera.screen_ray(100.0, 500.0);
+        let (x, y, _) = camera.project(&ray.at(30.0)).unwrap();
+        assert!((x - 100.0).abs() < 0.01 && (y - 500.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_ray_hits() {
+        let camera = camera();
+        let ray = camera.screen_ray(camera.extent.width as f32 / 2.0,
+                                    camera.extent.height as f32 / 2.0);
+        let center = Point3::new(0.0, 0.0, 10.0);
+        assert!((ray.hit_sphere(&center, 1.0).unwrap() - 9.0).abs() < 1e-4);
+        assert!(ray.hit_sphere(&Point3::new(5.0, 0.0, 10.0), 1.0).is_none());
+        assert!(ray.hit_sphere(&Point3::new(0.0, 0.0, -10.0), 1.0).is_none());
+
+        let s = 0.5_f32.sqrt();
+        let turned = [Vec3::new(s, 0.0, s), Vec3::new(0.0, 1.0, 0.0), Vec3::new(-s, 0.0, s)];
+        assert!((ray.hit_cuboid(&center, &turned).unwrap() - (10.0 - 2.0_f32.sqrt())).abs()
+                < 1e-4);
+        assert!(ray.hit_cuboid(&Point3::new(3.0, 0.0, 10.0), &turned).is_none());
+    }
+
+    #[test]
+    fn test_obb_containment() {
+        let camera = camera();
+        let s = 0.5_f32.sqrt();
+
+        // A 2 meter cube turned 45 degrees about y, straight ahead
+        let turned = [Vec3::new(s, 0.0, s), Vec3::new(0.0, 1.0, 0.0), Vec3::new(-s, 0.0, s)];
+        assert_eq!(camera.obb_containment(&Point3::new(0.0, 0.0, 10.0), &turned),
+                   Containment::Inside);
+        assert_eq!(camera.obb_containment(&Point3::new(0.0, 0.0, -10.0), &turned),
+                   Containment::Outside);
+
+        // A long thin diagonal beam across the view, with both ends well
+        // outside the frustum
+        let beam = [Vec3::new(500.0, 0.0, 500.0), Vec3::new(0.0, 0.1, 0.0),
+                    Vec3::new(-0.1, 0.0, 0.1)];
+        assert_eq!(camera.obb_containment(&Point3::new(0.0, 0.0, 20.0), &beam),
+                   Containment::Intersecting);
+
+        // The same beam, off to the right
+        assert_eq!(camera.obb_containment(&Point3::new(2000.0, 0.0, 0.0), &beam),
+                   Containment::Outside);
+    }
+}
@@ -0,0 +1,361 @@
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::Instant;
+use siege_math::{Angle, Point3, Vec3};
+use siege_plugin_avatar_simple::Placement;
+use toml;
+use errors::*;
+use state::State;
+use camera::Camera;
+use command::{CommandRegistry, Command, ArgSpec, ArgKind, Args};
+
+// Bump this when the format changes
+const VERSION: u32 = 1;
+
+// How many points along each segment we measure the path length at
+const SAMPLES_PER_SEGMENT: usize = 32;
+
+// Turning and zooming count toward the length of a path as if each degree
+// were this far, so that a path which only turns still takes some time
+const METERS_PER_DEGREE: f32 = 0.05;
+
+pub const DEFAULT_PATH_SPEED: f32 = 5.0;
+
+// How many frames a benchmark run takes by default
+const DEFAULT_BENCH_FRAMES: u32 = 1000;
+
+/// Where the camera was, at one point along a path. Angles are in degrees,
+/// so that path files can be edited by hand.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fov: f32,
+}
+
+impl Keyframe {
+    /// Where the camera is viewing from now
+    pub fn capture(camera: &Camera) -> Keyframe {
+        let p = camera.view_position;
+        Keyframe {
+            position: [p.x, p.y, p.z],
+            yaw: camera.view_yaw.to_degrees(),
+            pitch: camera.view_pitch.to_degrees(),
+            fov: camera.fovx.as_radians().to_degrees(),
+        }
+    }
+
+    fn channels(&self) -> [f32; 6] {
+        [self.position[0], self.position[1], self.position[2], self.yaw, self.pitch, self.fov]
+    }
+
+    fn from_channels(c: &[f32; 6]) -> Keyframe {
+        Keyframe { position: [c[0], c[1], c[2]], yaw: c[3], pitch: c[4], fov: c[5] }
+    }
+
+    pub fn placement(&self) -> Placement {
+        let p = self.position;
+        Placement::new(Point3(Vec3::new(p[0], p[1], p[2])),
+                       self.pitch.to_radians(), self.yaw.to_radians())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PathFile {
+    version: u32,
+    keyframes: Vec<Keyframe>,
+}
+
+// A Catmull-Rom spline through p1 and p2, at t from 0 to 1
+fn catmull_rom(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    let (t2, t3) = (t * t, t * t * t);
+    0.5 * (2.0 * p1
+           + (p2 - p0) * t
+           + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+           + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+/// How fast a path is played back
+#[derive(Debug, Clone, Copy)]
+pub enum Pace {
+    /// In meters per second of real time (turning counts as some distance
+    /// too, see METERS_PER_DEGREE)
+    Speed(f32),
+    /// In a fixed number of frames, the same distance apart however long
+    /// they take to draw (for comparing render timings)
+    Frames(u32),
+}
+
+/// Playing back a path, at a steady pace
+pub struct PathPlayback {
+    keyframes: Vec<[f32; 6]>,
+    // The distance along the path at evenly spaced spline parameters, where
+    // the parameter runs from 0 at the first keyframe to 1 at the next, etc.
+    lengths: Vec<f32>,
+    pace: Pace,
+    start: Instant,
+    frames: u32,
+    // The field of view to go back to when done
+    fov_before: Angle<f32>,
+}
+
+impl PathPlayback {
+    pub fn new(keyframes: &[Keyframe], pace: Pace, fov_before: Angle<f32>)
+               -> Result<PathPlayback>
+    {
+        if keyframes.len() < 2 {
+            return Err(ErrorKind::Command(
+                "A camera path needs at least two keyframes".to_owned()).into());
+        }
+        let mut channels: Vec<[f32; 6]> = keyframes.iter().map(|k| k.channels()).collect();
+
+        // Yaw wraps around, so turn the short way between keyframes
+        for i in 1..channels.len() {
+            let previous = channels[i - 1][3];
+            let mut delta = (channels[i][3] - previous) % 360.0;
+            if delta > 180.0 {
+                delta -= 360.0;
+            } else if delta < -180.0 {
+                delta += 360.0;
+            }
+            channels[i][3] = previous + delta;
+        }
+
+        let mut playback = PathPlayback {
+            keyframes: channels,
+            lengths: Vec::new(),
+            pace: pace,
+            start: Instant::now(),
+            frames: 0,
+            fov_before: fov_before,
+        };
+
+        // Measure the path, so that it can be followed at a steady speed
+        let samples = (playback.keyframes.len() - 1) * SAMPLES_PER_SEGMENT;
+        let mut length = 0.0;
+        let mut last = playback.sample(0.0);
+        playback.lengths.push(0.0);
+        for i in 1..(samples + 1) {
+            let here = playback.sample(i as f32 / SAMPLES_PER_SEGMENT as f32);
+            let mut d = [0.0; 6];
+            for c in 0..6 {
+                d[c] = here[c] - last[c];
+            }
+            let moved = (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt();
+            let turned = (d[3] * d[3] + d[4] * d[4] + d[5] * d[5]).sqrt();
+            length += moved + turned * METERS_PER_DEGREE;
+            playback.lengths.push(length);
+            last = here;
+        }
+        if length < 0.01 {
+            return Err(ErrorKind::Command(
+                "The camera path does not go anywhere".to_owned()).into());
+        }
+
+        Ok(playback)
+    }
+
+    // The spline at a parameter from 0 to (keyframes - 1)
+    fn sample(&self, u: f32) -> [f32; 6] {
+        let last = self.keyframes.len() - 1;
+        let segment = (u.floor() as usize).min(last - 1);
+        let t = u - segment as f32;
+        // (the ends are repeated, so the spline still reaches them)
+        let k = |i: isize| self.keyframes[(i.max(0) as usize).min(last)];
+        let i = segment as isize;
+        let (p0, p1, p2, p3) = (k(i - 1), k(i), k(i + 1), k(i + 2));
+        let mut out = [0.0; 6];
+        for c in 0..6 {
+            out[c] = catmull_rom(p0[c], p1[c], p2[c], p3[c], t);
+        }
+        out
+    }
+
+    // The spline parameter at a distance along the path
+    fn parameter_at(&self, distance: f32) -> f32 {
+        let i = match self.lengths.binary_search_by(
+            |l| l.partial_cmp(&distance).unwrap_or(::std::cmp::Ordering::Less))
+        {
+            Ok(i) => return i as f32 / SAMPLES_PER_SEGMENT as f32,
+            Err(i) => i.max(1).min(self.lengths.len() - 1),
+        };
+        let (before, after) = (self.lengths[i - 1], self.lengths[i]);
+        let within = if after > before { (distance - before) / (after - before) } else { 0.0 };
+        (i as f32 - 1.0 + within) / SAMPLES_PER_SEGMENT as f32
+    }
+
+    fn total_length(&self) -> f32 {
+        *self.lengths.last().unwrap()
+    }
+
+    /// Where the camera is now, or None if the path is done
+    fn at(&mut self, now: Instant) -> Option<Keyframe> {
+        let distance = match self.pace {
+            Pace::Speed(speed) => {
+                let elapsed = now.duration_since(self.start);
+                let seconds = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9;
+                seconds * speed
+            },
+            // (the first frame is at the start and the last at the end)
+            Pace::Frames(frames) if self.frames < frames =>
+                self.total_length() * self.frames as f32 / (frames - 1) as f32,
+            Pace::Frames(_) => return None,
+        };
+        if distance > self.total_length() {
+            return None;
+        }
+        self.frames += 1;
+        Some(Keyframe::from_channels(&self.sample(self.parameter_at(distance))))
+    }
+
+    // Describe how the run went (for comparing render timings)
+    fn summary(&self) -> String {
+        let elapsed = Instant::now().duration_since(self.start);
+        let seconds = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9;
+        format!("Camera path done: {} frames in {:.2} s ({:.2} ms per frame)",
+                self.frames, seconds, seconds * 1000.0 / self.frames.max(1) as f32)
+    }
+}
+
+/// Keyframes being put together, and the path being played back (if any)
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+    playback: Option<PathPlayback>,
+}
+
+impl CameraPath {
+    pub fn new() -> CameraPath {
+        CameraPath {
+            keyframes: Vec::new(),
+            playback: None,
+        }
+    }
+
+    /// Move the camera along the path being played back. Returns where to
+    /// view from, or None if no path is playing.
+    pub fn update(&mut self, camera: &mut Camera, now: Instant) -> Option<Placement> {
+        let frame = match self.playback {
+            Some(ref mut playback) => playback.at(now),
+            None => return None,
+        };
+        match frame {
+            Some(frame) => {
+                camera.set_fovx(Angle::from_degrees(frame.fov));
+                Some(frame.placement())
+            },
+            None => {
+                let playback = self.playback.take().unwrap();
+                info!("{}", playback.summary());
+                camera.set_fovx(playback.fov_before);
+                None
+            },
+        }
+    }
+
+    fn load(&mut self, path: &Path) -> Result<()> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+        let file: PathFile = toml::from_str(&*contents)?;
+        if file.version != VERSION {
+            return Err(ErrorKind::Command(
+                format!("{} is a version {} camera path, we play version {}",
+                        path.display(), file.version, VERSION)).into());
+        }
+        self.keyframes = file.keyframes;
+        Ok(())
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let file = PathFile {
+            version: VERSION,
+            keyframes: self.keyframes.clone(),
+        };
+        let contents = toml::to_string(&file)?;
+        File::create(path)?.write_all(contents.as_bytes())?;
+        Ok(())
+    }
+}
+
+const PATH_ACTIONS: &'static [&'static str] =
+    &["add", "clear", "save", "load", "play", "bench", "stop"];
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(Command::new(
+        "campath", vec![ArgSpec::required("action", ArgKind::Choice(PATH_ACTIONS)),
+                        ArgSpec::optional("arg", ArgKind::Rest)],
+        "Build a camera path from where the camera is (add), save or load it \
+         (with a file), or play it back (at a speed in m/s, or for benchmarks \
+         in a number of frames)", campath));
+}
+
+fn usage(text: &str) -> Error {
+    ErrorKind::Command(format!("Usage: campath {}", text)).into()
+}
+
+fn campath(state: &State, args: &Args) -> Result<Option<String>> {
+    let arg = if args.len() > 1 { Some(args.word(1).trim()) } else { None };
+    match args.word(0) {
+        "add" => {
+            // (the camera is locked before the path everywhere else too)
+            let keyframe = Keyframe::capture(&state.camera.read().unwrap());
+            let mut path = state.camera_path.lock().unwrap();
+            path.keyframes.push(keyframe);
+            Ok(Some(format!("Keyframe {} added", path.keyframes.len())))
+        },
+        "clear" => {
+            state.camera_path.lock().unwrap().keyframes.clear();
+            Ok(Some("Camera path cleared".to_owned()))
+        },
+        "save" => {
+            let file = Path::new(arg.ok_or_else(|| usage("save <file>"))?);
+            let path = state.camera_path.lock().unwrap();
+            path.save(file)?;
+            Ok(Some(format!("Saved {} keyframes to {}", path.keyframes.len(), file.display())))
+        },
+        "load" => {
+            let file = Path::new(arg.ok_or_else(|| usage("load <file>"))?);
+            let mut path = state.camera_path.lock().unwrap();
+            path.load(file)?;
+            Ok(Some(format!("Loaded {} keyframes from {}", path.keyframes.len(), file.display())))
+        },
+        "play" | "bench" => {
+            let pace = match (args.word(0), arg) {
+                ("play", Some(text)) => text.parse::<f32>().ok()
+                    .filter(|s| *s > 0.0 && s.is_finite())
+                    .map(Pace::Speed).ok_or_else(|| usage("play [speed]"))?,
+                ("play", None) => Pace::Speed(DEFAULT_PATH_SPEED),
+                (_, Some(text)) => text.parse::<u32>().ok().filter(|f| *f >= 2)
+                    .map(Pace::Frames).ok_or_else(|| usage("bench [frames]"))?,
+                (_, None) => Pace::Frames(DEFAULT_BENCH_FRAMES),
+            };
+            let fov = state.camera.read().unwrap().fovx;
+            let mut path = state.camera_path.lock().unwrap();
+            if path.playback.is_some() {
+                return Err(ErrorKind::Command(
+                    "A camera path is already playing".to_owned()).into());
+            }
+            let playback = PathPlayback::new(&path.keyframes, pace, fov)?;
+            let message = match pace {
+                Pace::Speed(speed) => format!("Playing a {:.1} m camera path at {} m/s",
+                                              playback.total_length(), speed),
+                Pace::Frames(frames) => format!("Playing a {:.1} m camera path in {} frames",
+                                                playback.total_length(), frames),
+            };
+            path.playback = Some(playback);
+            Ok(Some(message))
+        },
+        _ => {
+            let playback = state.camera_path.lock().unwrap().playback.take();
+            match playback {
+                Some(playback) => {
+                    state.camera.write().unwrap().set_fovx(playback.fov_before);
+                    Ok(Some(playback.summary()))
+                },
+                None => Ok(Some("No camera path is playing".to_owned())),
+            }
+        },
+    }
+}
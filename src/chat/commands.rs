@@ -0,0 +1,114 @@
+
+use command::{CommandRegistry, Command, ArgSpec, ArgKind, Args};
+use errors::*;
+use state::State;
+use super::ChatFilter;
+
+const ON_OFF: &'static [&'static str] = &["on", "off"];
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(Command::new(
+        "ignore", vec![ArgSpec::required("name", ArgKind::Word)],
+        "Hide chat messages from a player", ignore));
+    registry.register(Command::new(
+        "unignore", vec![ArgSpec::required("name", ArgKind::Word)],
+        "Stop hiding chat messages from a player", unignore));
+    registry.register(Command::new(
+        "ignored", vec![], "List ignored players", ignored));
+    registry.register(Command::new(
+        "mask", vec![ArgSpec::required("word", ArgKind::Word)],
+        "Mask a word in chat messages", mask));
+    registry.register(Command::new(
+        "unmask", vec![ArgSpec::required("word", ArgKind::Word)],
+        "Stop masking a word in chat messages", unmask));
+    registry.register(Command::new(
+        "masked", vec![], "List masked words", masked));
+    registry.register(Command::new(
+        "masking", vec![ArgSpec::optional("state", ArgKind::Choice(ON_OFF))],
+        "Show, or turn on or off, word masking", masking));
+    registry.register(Command::new(
+        "collapse", vec![ArgSpec::optional("state", ArgKind::Choice(ON_OFF))],
+        "Show, or turn on or off, collapsing of repeated messages", collapse));
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
+
+fn list(what: &str, items: Vec<&str>) -> String {
+    if items.is_empty() {
+        format!("No {}", what)
+    } else {
+        format!("{}: {}", what, items.join(", "))
+    }
+}
+
+fn ignore(state: &State, args: &Args) -> Result<Option<String>> {
+    let name = args.word(0);
+    let added = state.chat.write().unwrap().update_filter(|f| f.ignore(name));
+    Ok(Some(if added {
+        format!("Ignoring {}", name)
+    } else {
+        format!("Already ignoring {}", name)
+    }))
+}
+
+fn unignore(state: &State, args: &Args) -> Result<Option<String>> {
+    let name = args.word(0);
+    let removed = state.chat.write().unwrap().update_filter(|f| f.unignore(name));
+    Ok(Some(if removed {
+        format!("No longer ignoring {}", name)
+    } else {
+        format!("{} was not being ignored", name)
+    }))
+}
+
+fn ignored(state: &State, _args: &Args) -> Result<Option<String>> {
+    let chat = state.chat.read().unwrap();
+    let filter: &ChatFilter = chat.filter();
+    Ok(Some(list("Ignored players", filter.ignored.iter().map(|s| &**s).collect())))
+}
+
+fn mask(state: &State, args: &Args) -> Result<Option<String>> {
+    let word = args.word(0);
+    let added = state.chat.write().unwrap().update_filter(|f| f.mask_word(word));
+    Ok(Some(if added {
+        format!("Masking {}", word)
+    } else {
+        format!("Already masking {}", word)
+    }))
+}
+
+fn unmask(state: &State, args: &Args) -> Result<Option<String>> {
+    let word = args.word(0);
+    let removed = state.chat.write().unwrap().update_filter(|f| f.unmask_word(word));
+    Ok(Some(if removed {
+        format!("No longer masking {}", word)
+    } else {
+        format!("{} was not being masked", word)
+    }))
+}
+
+fn masked(state: &State, _args: &Args) -> Result<Option<String>> {
+    let chat = state.chat.read().unwrap();
+    let filter: &ChatFilter = chat.filter();
+    Ok(Some(list("Masked words", filter.masked_words.iter().map(|s| &**s).collect())))
+}
+
+fn masking(state: &State, args: &Args) -> Result<Option<String>> {
+    let mut chat = state.chat.write().unwrap();
+    if args.len() == 1 {
+        let on = args.word(0) == "on";
+        chat.update_filter(|f| f.masking = on);
+    }
+    Ok(Some(format!("Word masking is {}", on_off(chat.filter().masking))))
+}
+
+fn collapse(state: &State, args: &Args) -> Result<Option<String>> {
+    let mut chat = state.chat.write().unwrap();
+    if args.len() == 1 {
+        let on = args.word(0) == "on";
+        chat.update_filter(|f| f.collapse_repeats = on);
+    }
+    Ok(Some(format!("Collapsing repeats is {}", on_off(chat.filter().collapse_repeats))))
+}
@@ -0,0 +1,108 @@
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use toml;
+use errors::*;
+
+#[inline] fn default_true() -> bool { true }
+
+/// Client-side filtering of chat messages from other players.
+/// Names and words are stored in lowercase, and matched case-insensitively.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatFilter {
+    #[serde(default)]
+    pub ignored: BTreeSet<String>,
+    #[serde(default)]
+    pub masked_words: BTreeSet<String>,
+    #[serde(default = "default_true")]
+    pub masking: bool,
+    #[serde(default = "default_true")]
+    pub collapse_repeats: bool,
+}
+
+impl Default for ChatFilter {
+    fn default() -> ChatFilter {
+        ChatFilter {
+            ignored: BTreeSet::new(),
+            masked_words: BTreeSet::new(),
+            masking: true,
+            collapse_repeats: true,
+        }
+    }
+}
+
+impl ChatFilter {
+    /// Load the filter from a file. A missing file gives an empty filter.
+    pub fn load(path: &Path) -> Result<ChatFilter>
+    {
+        if !path.is_file() {
+            return Ok(Default::default());
+        }
+        let mut contents: String = String::new();
+        let mut file = File::open(path)?;
+        file.read_to_string(&mut contents)?;
+        Ok(toml::from_str(&*contents)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()>
+    {
+        let contents = toml::to_string(self)?;
+        let mut file = File::create(path)?;
+        file.write_all(contents.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn is_ignored(&self, sender: &str) -> bool {
+        self.ignored.contains(&sender.to_lowercase())
+    }
+
+    /// Replace masked words with asterisks (whole words only)
+    pub fn mask(&self, text: &str) -> String {
+        if !self.masking || self.masked_words.is_empty() {
+            return text.to_owned();
+        }
+
+        let mut output = String::with_capacity(text.len());
+        let mut word = String::new();
+        let flush = |word: &mut String, output: &mut String| {
+            if self.masked_words.contains(&word.to_lowercase()) {
+                output.extend(word.chars().map(|_| '*'));
+            } else {
+                output.push_str(&*word);
+            }
+            word.clear();
+        };
+        for ch in text.chars() {
+            if ch.is_alphanumeric() {
+                word.push(ch);
+            } else {
+                flush(&mut word, &mut output);
+                output.push(ch);
+            }
+        }
+        flush(&mut word, &mut output);
+        output
+    }
+
+    /// Returns true if the name was not already ignored
+    pub fn ignore(&mut self, sender: &str) -> bool {
+        self.ignored.insert(sender.to_lowercase())
+    }
+
+    /// Returns true if the name was being ignored
+    pub fn unignore(&mut self, sender: &str) -> bool {
+        self.ignored.remove(&sender.to_lowercase())
+    }
+
+    /// Returns true if the word was not already masked
+    pub fn mask_word(&mut self, word: &str) -> bool {
+        self.masked_words.insert(word.to_lowercase())
+    }
+
+    /// Returns true if the word was masked
+    pub fn unmask_word(&mut self, word: &str) -> bool {
+        self.masked_words.remove(&word.to_lowercase())
+    }
+}
@@ -0,0 +1,149 @@
+
+use std::sync::Arc;
+use winit::VirtualKeyCode as Key;
+use ui::{Ui, UiEvent, Handle};
+use state::State;
+use super::{Chat, Channel};
+
+/// A line being typed into the chat window
+pub struct ChatInput {
+    /// Whether it is a command (typed without the leading slash)
+    command: bool,
+    line: String,
+}
+
+impl ChatInput {
+    // The line as shown in the window
+    fn shown(&self) -> String {
+        if self.command {
+            format!("/{}", self.line)
+        } else {
+            self.line.clone()
+        }
+    }
+}
+
+impl Chat {
+    // Start typing a line. Chat stays visible while the user is typing into it.
+    fn begin_input(&mut self, ui: &Ui, command: bool) {
+        self.input = Some(ChatInput { command: command, line: String::new() });
+        self.set_active(ui, true);
+        self.refresh(ui);
+    }
+
+    // Stop typing, returning what was typed
+    fn end_input(&mut self, ui: &Ui) -> Option<ChatInput> {
+        let input = self.input.take();
+        self.set_active(ui, false);
+        self.refresh(ui);
+        input
+    }
+
+    fn edit_input<F: FnOnce(&mut ChatInput)>(&mut self, ui: &Ui, f: F) {
+        if let Some(ref mut input) = self.input {
+            f(input);
+        }
+        self.refresh(ui);
+    }
+}
+
+/// Let the chat window take the keyboard focus, and type a line into it while
+/// it has it. Clicking the window or tabbing to it starts a chat line.
+pub fn attach_input(state: &Arc<State>) {
+    let handle = state.chat.read().unwrap().win_handle;
+    // (weakly, since the ui belongs to the state)
+    let weak = Arc::downgrade(state);
+    state.ui.add_listener(handle, Arc::new(move |_ui: &Ui, _handle: Handle, event: &UiEvent| {
+        match weak.upgrade() {
+            Some(state) => handle_event(&state, event),
+            None => false,
+        }
+    }));
+    state.ui.set_focusable(handle, true);
+}
+
+/// Start typing a chat line (or a command) into the chat window
+pub fn start_typing(state: &State, command: bool) {
+    let handle = {
+        let mut chat = state.chat.write().unwrap();
+        chat.begin_input(&state.ui, command);
+        chat.win_handle
+    };
+    state.ui.set_focus(Some(handle));
+}
+
+fn handle_event(state: &State, event: &UiEvent) -> bool {
+    match *event {
+        UiEvent::FocusGained => {
+            let mut chat = state.chat.write().unwrap();
+            if chat.input.is_none() {
+                chat.begin_input(&state.ui, false);
+            }
+            true
+        },
+        UiEvent::FocusLost => {
+            // Whatever was typed is dropped
+            state.chat.write().unwrap().end_input(&state.ui);
+            true
+        },
+        UiEvent::KeyDown { key, modifiers } => {
+            // Leave chords for the bindings
+            if modifiers.ctrl || modifiers.alt || modifiers.logo {
+                return false;
+            }
+            match key {
+                Key::Return => {
+                    let input = state.chat.write().unwrap().end_input(&state.ui);
+                    state.ui.set_focus(None);
+                    match input {
+                        Some(ChatInput { command: true, line }) => state.run_command(&*line),
+                        Some(ChatInput { command: false, line }) => state.say(&*line),
+                        None => {},
+                    }
+                },
+                Key::Escape => state.ui.set_focus(None),
+                Key::Back => state.chat.write().unwrap().edit_input(
+                    &state.ui, |input| { input.line.pop(); }),
+                // Tab completes commands. Shift-Tab, or Tab with nothing to
+                // complete, moves the focus on.
+                Key::Tab => return !modifiers.shift && complete(state),
+                _ => {},
+            }
+            // Every other key types (or does nothing), rather than reaching
+            // the bindings
+            true
+        },
+        UiEvent::Character(ch) => {
+            // (control characters come as keys, above)
+            if !ch.is_control() {
+                state.chat.write().unwrap().edit_input(&state.ui, |input| input.line.push(ch));
+            }
+            true
+        },
+        _ => false,
+    }
+}
+
+// Complete the command being typed, listing the candidates if there are
+// several. Returns false if there is nothing to complete.
+fn complete(state: &State) -> bool {
+    // In a chat line, only commands can be completed
+    let (prefix, line) = match state.chat.read().unwrap().input {
+        Some(ChatInput { command: true, ref line }) => ("", line.clone()),
+        Some(ChatInput { command: false, ref line }) if line.starts_with('/') =>
+            ("/", line[1..].to_owned()),
+        _ => return false,
+    };
+    let completion = state.commands.read().unwrap().complete(&state.cvars, &*line);
+    if completion.line == line && completion.candidates.is_empty() {
+        return false;
+    }
+
+    let mut chat = state.chat.write().unwrap();
+    for names in completion.candidates.chunks(6) {
+        chat.emit_line(&state.ui, Channel::System, &*names.join("  "));
+    }
+    let completed = format!("{}{}", prefix, completion.line);
+    chat.edit_input(&state.ui, |input| input.line = completed);
+    true
+}
@@ -0,0 +1,109 @@
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use chrono::{Local, DateTime, Utc};
+use errors::*;
+use super::Channel;
+
+const PREFIX: &'static str = "chat-";
+const SUFFIX: &'static str = ".log";
+
+/// A per-session chat log file. Each line is written as
+///   <rfc3339 timestamp> [<channel>] <text>
+pub struct ChatLog {
+    path: PathBuf,
+    file: File,
+}
+
+impl ChatLog {
+    /// Create a new log file for this session in the given directory
+    pub fn new(directory: &Path) -> Result<ChatLog>
+    {
+        fs::create_dir_all(directory)?;
+
+        let filename = format!("{}{}{}", PREFIX,
+                               Local::now().format("%Y%m%d-%H%M%S"), SUFFIX);
+        let path = directory.join(filename);
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+
+        Ok(ChatLog {
+            path: path,
+            file: file,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write(&mut self, channel: Channel, text: &str) -> Result<()>
+    {
+        let now: DateTime<Utc> = Utc::now();
+        writeln!(self.file, "{} [{}] {}", now.to_rfc3339(), channel.name(), text)?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// Load the last `count` lines of the most recent log file in the directory.
+    /// This must be called before a new log is created, or it will find that one.
+    pub fn load_previous(directory: &Path, count: usize) -> Result<Vec<(Channel, String)>>
+    {
+        if count == 0 || !directory.is_dir() {
+            return Ok(vec![]);
+        }
+
+        // Filenames sort in time order, so the last one is the most recent
+        let mut latest: Option<PathBuf> = None;
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            let is_log = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name.starts_with(PREFIX) && name.ends_with(SUFFIX),
+                None => false
+            };
+            if is_log && latest.as_ref().map(|l| path > *l).unwrap_or(true) {
+                latest = Some(path);
+            }
+        }
+        let latest = match latest {
+            Some(l) => l,
+            None => return Ok(vec![]),
+        };
+
+        let reader = BufReader::new(File::open(&latest)?);
+        let mut lines: Vec<(Channel, String)> = Vec::new();
+        for line in reader.lines() {
+            if let Some(parsed) = parse_line(&*line?) {
+                lines.push(parsed);
+            }
+        }
+
+        let skip = lines.len().saturating_sub(count);
+        Ok(lines.split_off(skip))
+    }
+}
+
+fn parse_line(line: &str) -> Option<(Channel, String)>
+{
+    // Skip the timestamp
+    let rest = match line.find(' ') {
+        Some(i) => &line[i+1..],
+        None => return None,
+    };
+    if !rest.starts_with('[') {
+        return None;
+    }
+    let close = match rest.find("] ") {
+        Some(i) => i,
+        None => return None,
+    };
+    let channel = match Channel::from_name(&rest[1..close]) {
+        Some(c) => c,
+        None => return None,
+    };
+    Some((channel, rest[close+2..].to_owned()))
+}
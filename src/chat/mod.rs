@@ -0,0 +1,431 @@
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::Instant;
+use errors::*;
+use ui::{Ui, UiWindow, StyledLine, TextSpan, TextStyle, Point, TextColor, Font, Dim,
+         Coord, Rect, RectX, RectY, UiElement, Handle, WINDOW_TOP, WINDOW_BOTTOM,
+         parse_markup, plain_text};
+use config::ChatConfig;
+
+mod log;
+use self::log::ChatLog;
+
+mod filter;
+pub use self::filter::ChatFilter;
+
+mod commands;
+pub use self::commands::register_commands;
+
+mod input;
+use self::input::ChatInput;
+pub use self::input::{attach_input, start_typing};
+
+// How many lines we remember (more than can ever be visible)
+const MAX_SCROLLBACK: usize = 500;
+
+// The style of chat text, unless markup changes it
+const BASE_STYLE: TextStyle = TextStyle {
+    color: TextColor::White,
+    outline: None,
+    font: Font::Mono,
+};
+
+// The most recent message from a player, for collapsing repeats
+struct LastMessage {
+    channel: Channel,
+    sender: String,
+    text: String,
+    count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    System,
+    Say,
+    /// Log messages (see logger.rs)
+    Console,
+}
+
+impl Channel {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Channel::System => "system",
+            Channel::Say => "say",
+            Channel::Console => "console",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Channel> {
+        match name {
+            "system" => Some(Channel::System),
+            "say" => Some(Channel::Say),
+            "console" => Some(Channel::Console),
+            _ => None,
+        }
+    }
+}
+
+/// FIXME: this is a text-scrolling terminal, often used for chat windows,
+/// but does not implement any 'chat' service - so rename it.
+pub struct Chat {
+    /// Lines of text, oldest first
+    lines: VecDeque<Vec<TextSpan>>,
+    /// Text line handles, slot 0 is the bottom line. There may be more slots
+    /// than visible lines if the window has shrunk.
+    slots: Vec<Handle>,
+    visible_lines: usize,
+    /// The window height that visible_lines was worked out for
+    outer_height: f32,
+    line_height: u8,
+    line_drop: i32,
+    fade_after: f32,
+    fade_duration: f32,
+    fade_min_alpha: u8,
+    /// When the last line arrived (or chat was last active)
+    last_activity: Instant,
+    /// While active (the user is typing), the lines do not fade
+    active: bool,
+    alpha: u8,
+    /// The line the user is typing, shown below the other lines
+    input: Option<ChatInput>,
+    filter: ChatFilter,
+    filter_file: PathBuf,
+    last_message: Option<LastMessage>,
+    pub inner_win_handle: Handle,
+    pub win_handle: Handle,
+    pub win: UiWindow,
+    log: Option<ChatLog>,
+}
+
+impl Chat {
+    pub fn new(ui: &Ui, config: &ChatConfig) -> Chat
+    {
+        let margin = config.margin;
+        let win = UiWindow::new(
+            Rect::new(RectX::LeftWidth(Coord::near(0.0, margin),
+                                       Dim::new(config.width_fraction, config.width)),
+                      RectY::BottomHeight(Coord::far(0.0, -margin),
+                                          Dim::new(config.height_fraction, config.height))),
+            [0.0, 0.0, 0.0, 0.0], 0.8
+        );
+        let win_handle = ui.add_element(UiElement::Window(win.clone()), None).unwrap();
+
+        let inner_win_handle = UiWindow::decorate_window(
+            ui, win_handle, vec![], Some("Chat"));
+        UiWindow::make_movable(ui, win_handle, "chat");
+
+        let mut chat = Chat {
+            lines: VecDeque::new(),
+            slots: vec![],
+            visible_lines: 0,
+            outer_height: 0.0,
+            line_height: config.line_height,
+            line_drop: config.line_drop,
+            fade_after: config.fade_after,
+            fade_duration: config.fade_duration,
+            fade_min_alpha: config.fade_min_alpha,
+            last_activity: Instant::now(),
+            active: false,
+            alpha: 255,
+            input: None,
+            filter: match ChatFilter::load(&config.filter_file) {
+                Ok(filter) => filter,
+                Err(e) => {
+                    warn!("Unable to load chat filters: {}", e);
+                    Default::default()
+                }
+            },
+            filter_file: config.filter_file.clone(),
+            last_message: None,
+            inner_win_handle: inner_win_handle,
+            win_handle: win_handle,
+            win: win,
+            log: None,
+        };
+
+        chat.resize(ui);
+
+        if config.log {
+            // Reload the tail of the previous session before we start a new log
+            match ChatLog::load_previous(&config.log_directory, config.reload_lines) {
+                Ok(lines) => for (channel, text) in lines {
+                    chat.push_line(reloaded_spans(channel, text));
+                },
+                Err(e) => warn!("Unable to reload chat log: {}", e),
+            }
+            chat.refresh(ui);
+
+            match ChatLog::new(&config.log_directory) {
+                Ok(log) => {
+                    info!("Logging chat to {}", log.path().display());
+                    chat.log = Some(log);
+                },
+                Err(e) => warn!("Unable to open chat log: {}", e),
+            }
+        }
+
+        chat
+    }
+
+    pub fn emit_line<'a>(&mut self, ui: &Ui, channel: Channel, text: &'a str) {
+        let span = TextSpan { style: BASE_STYLE, text: text.to_owned() };
+        self.emit_spans(ui, channel, vec![span]);
+    }
+
+    /// Emit a line containing style markup (see `ui::parse_markup`). Do not use this
+    /// for text that came from other players.
+    pub fn emit_markup<'a>(&mut self, ui: &Ui, channel: Channel, markup: &'a str) {
+        self.emit_spans(ui, channel, parse_markup(markup, BASE_STYLE));
+    }
+
+    /// Emit an error message for the user, in red
+    pub fn emit_error<'a>(&mut self, ui: &Ui, text: &'a str) {
+        self.emit_colored(ui, Channel::System, TextColor::Red, text);
+    }
+
+    /// Emit a line in a single color
+    pub fn emit_colored<'a>(&mut self, ui: &Ui, channel: Channel, color: TextColor,
+                            text: &'a str)
+    {
+        let style = TextStyle { color: color, ..BASE_STYLE };
+        let span = TextSpan { style: style, text: text.to_owned() };
+        self.emit_spans(ui, channel, vec![span]);
+    }
+
+    pub fn emit_spans(&mut self, ui: &Ui, channel: Channel, spans: Vec<TextSpan>) {
+        self.end_repeats();
+        self.write_log(channel, &*plain_text(&*spans));
+        self.push_line(spans);
+        self.refresh(ui);
+        self.wake(ui);
+    }
+
+    /// Emit a message from a player. The message passes through the filters
+    /// first, so it may be dropped, masked, or collapsed into the previous line.
+    pub fn emit_message(&mut self, ui: &Ui, channel: Channel, sender: &str, text: &str) {
+        if self.filter.is_ignored(sender) {
+            return;
+        }
+        let text = self.filter.mask(text);
+
+        let count = match self.last_message {
+            Some(ref last) if self.filter.collapse_repeats
+                && last.channel == channel && last.sender == sender && last.text == text
+                => last.count + 1,
+            _ => 1,
+        };
+
+        let spans = message_spans(sender, &*text, count);
+        if count > 1 {
+            // Replace the previous line rather than adding another. The log
+            // already has the line, and gets the count when the repeats end.
+            if let Some(line) = self.lines.back_mut() {
+                *line = spans;
+            }
+            self.refresh(ui);
+            self.wake(ui);
+        } else {
+            self.emit_spans(ui, channel, spans);
+        }
+
+        self.last_message = Some(LastMessage {
+            channel: channel,
+            sender: sender.to_owned(),
+            text: text,
+            count: count,
+        });
+    }
+
+    pub fn filter(&self) -> &ChatFilter {
+        &self.filter
+    }
+
+    /// Change the chat filters, and save them
+    pub fn update_filter<F, R>(&mut self, f: F) -> R
+        where F: FnOnce(&mut ChatFilter) -> R
+    {
+        let result = f(&mut self.filter);
+        if let Err(e) = self.filter.save(&self.filter_file) {
+            warn!("Unable to save chat filters: {}", e);
+        }
+        result
+    }
+
+    // Log how many times the last message was repeated, if it was
+    fn end_repeats(&mut self) {
+        if let Some(last) = self.last_message.take() {
+            if last.count > 1 {
+                let spans = message_spans(&*last.sender, &*last.text, last.count);
+                self.write_log(last.channel, &*plain_text(&*spans));
+            }
+        }
+    }
+
+    fn write_log(&mut self, channel: Channel, text: &str) {
+        let failed = match self.log {
+            Some(ref mut log) => match log.write(channel, text) {
+                Ok(_) => false,
+                Err(e) => {
+                    warn!("Unable to write chat log, logging disabled: {}", e);
+                    true
+                }
+            },
+            None => false,
+        };
+        if failed {
+            self.log = None;
+        }
+    }
+
+    /// Mark chat as active (or not). Lines stay fully opaque while active, and
+    /// start their fade timer over when chat becomes inactive.
+    pub fn set_active(&mut self, ui: &Ui, active: bool) {
+        self.active = active;
+        self.wake(ui);
+    }
+
+    /// Fade the lines if there has been no activity for a while.
+    /// Call this periodically.
+    pub fn update(&mut self, ui: &Ui) {
+        // The user may have resized the window
+        if ui.absolute_rect(self.win_handle).map(|r| r.height) != Some(self.outer_height) {
+            self.resize(ui);
+        }
+
+        if self.active || self.fade_after <= 0.0 {
+            return;
+        }
+
+        let elapsed = {
+            let d = Instant::now().duration_since(self.last_activity);
+            d.as_secs() as f32 + d.subsec_nanos() as f32 * 1e-9
+        };
+        let progress = if elapsed < self.fade_after {
+            0.0
+        } else if self.fade_duration <= 0.0 {
+            1.0
+        } else {
+            ((elapsed - self.fade_after) / self.fade_duration).min(1.0)
+        };
+        let min = self.fade_min_alpha as f32;
+        let alpha = (255.0 - (255.0 - min) * progress) as u8;
+        self.set_alpha(ui, alpha);
+    }
+
+    fn wake(&mut self, ui: &Ui) {
+        self.last_activity = Instant::now();
+        self.set_alpha(ui, 255);
+    }
+
+    fn set_alpha(&mut self, ui: &Ui, alpha: u8) {
+        if alpha == self.alpha {
+            return;
+        }
+        self.alpha = alpha;
+        for handle in &self.slots {
+            ui.set_alpha(*handle, alpha);
+        }
+    }
+
+    /// Recompute how many lines fit in the chat window, and lay them out again.
+    /// Call this whenever the screen size changes (window resizes by the user
+    /// are noticed in `update`).
+    pub fn resize(&mut self, ui: &Ui) {
+        let outer = match ui.absolute_rect(self.win_handle) {
+            Some(rect) => rect,
+            None => self.win.rect.absolute(&ui.screen()),
+        };
+        self.outer_height = outer.height;
+        let inner_height = outer.height - WINDOW_TOP.height - WINDOW_BOTTOM.height;
+        let usable = inner_height as i32 - self.line_drop;
+        self.visible_lines = (usable / self.line_height as i32).max(1) as usize;
+
+        // Add text lines if we need more than we have
+        while self.slots.len() < self.visible_lines {
+            let slot = self.slots.len();
+            let handle = ui.add_element(UiElement::Styled(StyledLine {
+                ui_coordinates: Point { x: Coord::near(0.0, 0), y: self.slot_y(slot) },
+                lineheight: self.line_height,
+                alpha: self.alpha,
+                spans: vec![],
+            }), Some(self.inner_win_handle)).unwrap();
+            self.slots.push(handle);
+        }
+
+        self.refresh(ui);
+    }
+
+    fn push_line(&mut self, spans: Vec<TextSpan>) {
+        self.lines.push_back(spans);
+        while self.lines.len() > MAX_SCROLLBACK {
+            self.lines.pop_front();
+        }
+    }
+
+    fn slot_y(&self, slot: usize) -> Coord {
+        Coord::far(0.0, -self.line_drop - (self.line_height as i32 * slot as i32))
+    }
+
+    // Write the input line and the most recent lines into the visible slots
+    fn refresh(&self, ui: &Ui) {
+        let first_line_slot = if self.input.is_some() { 1 } else { 0 };
+        for (slot, handle) in self.slots.iter().enumerate() {
+            let spans = if slot >= self.visible_lines {
+                vec![]
+            } else if slot < first_line_slot {
+                input_spans(&*self.input.as_ref().unwrap().shown())
+            } else if slot - first_line_slot < self.lines.len() {
+                self.lines[self.lines.len() - 1 - (slot - first_line_slot)].clone()
+            } else {
+                vec![]
+            };
+            ui.set_spans(*handle, spans);
+        }
+    }
+}
+
+impl Drop for Chat {
+    fn drop(&mut self) {
+        // (a run of repeats may still be going)
+        self.end_repeats();
+    }
+}
+
+fn message_spans(sender: &str, text: &str, count: usize) -> Vec<TextSpan> {
+    let mut spans = vec![
+        TextSpan {
+            style: TextStyle { color: TextColor::Gold, ..BASE_STYLE },
+            text: sender.to_owned()
+        },
+        TextSpan { style: BASE_STYLE, text: format!(": {}", text) },
+    ];
+    if count > 1 {
+        spans.push(TextSpan {
+            style: TextStyle { color: TextColor::Gray, ..BASE_STYLE },
+            text: format!(" x{}", count)
+        });
+    }
+    spans
+}
+
+// A line reloaded from the log, styled as its channel is
+fn reloaded_spans(channel: Channel, text: String) -> Vec<TextSpan> {
+    match channel {
+        Channel::Say => match text.find(": ") {
+            Some(i) => message_spans(&text[..i], &text[i + 2..], 1),
+            None => vec![TextSpan { style: BASE_STYLE, text: text }],
+        },
+        Channel::System | Channel::Console => vec![TextSpan { style: BASE_STYLE, text: text }],
+    }
+}
+
+fn input_spans(input: &str) -> Vec<TextSpan> {
+    vec![
+        TextSpan {
+            style: TextStyle { color: TextColor::Green, ..BASE_STYLE },
+            text: "> ".to_owned()
+        },
+        TextSpan { style: BASE_STYLE, text: format!("{}_", input) },
+    ]
+}
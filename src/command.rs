@@ -0,0 +1,366 @@
+
+use std::collections::BTreeMap;
+use std::collections::btree_map::Values;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use errors::*;
+use state::State;
+use cvar::CvarRegistry;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgKind {
+    Float,
+    Int,
+    Word,
+    /// One of a fixed set of words
+    Choice(&'static [&'static str]),
+    /// Everything to the end of the line
+    Rest,
+    /// The name of a command
+    CommandName,
+    /// The name of a cvar
+    CvarName,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub optional: bool,
+}
+
+impl ArgSpec {
+    pub fn required(name: &'static str, kind: ArgKind) -> ArgSpec {
+        ArgSpec { name: name, kind: kind, optional: false }
+    }
+
+    pub fn optional(name: &'static str, kind: ArgKind) -> ArgSpec {
+        ArgSpec { name: name, kind: kind, optional: true }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Arg {
+    Float(f32),
+    Int(i64),
+    Word(String),
+}
+
+/// Parsed arguments. The accessors panic if the command's ArgSpecs do not
+/// match how they are used (this is a programming error, not a user error).
+#[derive(Debug, Clone)]
+pub struct Args(Vec<Arg>);
+
+#[allow(dead_code)]
+impl Args {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn float(&self, i: usize) -> f32 {
+        match self.0[i] {
+            Arg::Float(f) => f,
+            ref a => panic!("Argument {} is not a float: {:?}", i, a),
+        }
+    }
+
+    pub fn int(&self, i: usize) -> i64 {
+        match self.0[i] {
+            Arg::Int(n) => n,
+            ref a => panic!("Argument {} is not an int: {:?}", i, a),
+        }
+    }
+
+    pub fn word(&self, i: usize) -> &str {
+        match self.0[i] {
+            Arg::Word(ref w) => &*w,
+            ref a => panic!("Argument {} is not a word: {:?}", i, a),
+        }
+    }
+}
+
+/// Handlers return text to show the user (if any)
+pub type Handler = fn(&State, &Args) -> Result<Option<String>>;
+
+pub struct Command {
+    pub name: &'static str,
+    pub args: Vec<ArgSpec>,
+    pub help: &'static str,
+    pub handler: Handler,
+}
+
+impl Command {
+    pub fn new(name: &'static str, args: Vec<ArgSpec>, help: &'static str,
+               handler: Handler) -> Command
+    {
+        Command {
+            name: name,
+            args: args,
+            help: help,
+            handler: handler,
+        }
+    }
+
+    pub fn usage(&self) -> String {
+        let mut usage = self.name.to_owned();
+        for arg in &self.args {
+            let name = match arg.kind {
+                ArgKind::Choice(choices) => choices.join("|"),
+                ArgKind::Rest => format!("{}...", arg.name),
+                _ => arg.name.to_owned(),
+            };
+            if arg.optional {
+                usage.push_str(&*format!(" [{}]", name));
+            } else {
+                usage.push_str(&*format!(" <{}>", name));
+            }
+        }
+        usage
+    }
+
+    fn parse_args(&self, words: &[(&str, &str)]) -> Result<Args> {
+        let usage_err = || -> Error {
+            ErrorKind::Command(format!("Usage: {}", self.usage())).into()
+        };
+
+        let mut args: Vec<Arg> = Vec::new();
+        let mut i = 0;
+        for spec in &self.args {
+            let (word, rest) = match words.get(i) {
+                Some(&w) => w,
+                None if spec.optional => break,
+                None => return Err(usage_err()),
+            };
+            i += 1;
+            args.push(match spec.kind {
+                ArgKind::Float => {
+                    // ("nan" and "inf" parse, but are no use to anyone)
+                    let value: f32 = word.parse().map_err(|_| usage_err())?;
+                    if !value.is_finite() {
+                        return Err(usage_err());
+                    }
+                    Arg::Float(value)
+                },
+                ArgKind::Int => Arg::Int(word.parse().map_err(|_| usage_err())?),
+                ArgKind::Word | ArgKind::CommandName | ArgKind::CvarName =>
+                    Arg::Word(word.to_owned()),
+                ArgKind::Choice(choices) => {
+                    if !choices.contains(&word) {
+                        return Err(usage_err());
+                    }
+                    Arg::Word(word.to_owned())
+                },
+                ArgKind::Rest => {
+                    i = words.len();
+                    Arg::Word(rest.to_owned())
+                },
+            });
+        }
+        if i < words.len() {
+            return Err(usage_err());
+        }
+        Ok(Args(args))
+    }
+}
+
+/// Split a line into words. Each word comes with the rest of the line
+/// starting at that word.
+pub fn split_words(line: &str) -> Vec<(&str, &str)> {
+    let mut output = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        output.push((&rest[..end], rest));
+        rest = rest[end..].trim_start();
+    }
+    output
+}
+
+// The longest prefix that all of the words share
+fn common_prefix<'a>(words: &[&'a str]) -> &'a str {
+    let first = words[0];
+    let mut end = first.len();
+    for word in &words[1..] {
+        let shared = first.char_indices()
+            .zip(word.chars())
+            .take_while(|&((_, a), b)| a == b)
+            .map(|((i, a), _)| i + a.len_utf8())
+            .last()
+            .unwrap_or(0);
+        end = end.min(shared);
+    }
+    &first[..end]
+}
+
+/// The result of completing a command line
+pub struct Completion {
+    /// The line, extended as far as all of the candidates agree
+    pub line: String,
+    /// The candidates, if there was more than one
+    pub candidates: Vec<String>,
+}
+
+pub struct CommandRegistry {
+    commands: BTreeMap<&'static str, Command>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> CommandRegistry {
+        CommandRegistry {
+            commands: BTreeMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, command: Command) {
+        if self.commands.contains_key(command.name) {
+            warn!("Command {} registered twice", command.name);
+        }
+        self.commands.insert(command.name, command);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Command> {
+        self.commands.get(name)
+    }
+
+    pub fn iter(&self) -> Values<&'static str, Command> {
+        self.commands.values()
+    }
+
+    /// Complete the last word of a command line (without the leading slash).
+    /// Command names, cvar names and enumerated arguments are completed.
+    pub fn complete(&self, cvars: &CvarRegistry, line: &str) -> Completion {
+        let words = split_words(line);
+
+        // Either finish the last word, or start a new one
+        let (index, prefix) = match words.last() {
+            Some(&(word, _)) if !line.ends_with(char::is_whitespace) =>
+                (words.len() - 1, word),
+            _ => (words.len(), ""),
+        };
+
+        let names: Vec<&str> = if index == 0 {
+            self.commands.keys().cloned().collect()
+        } else {
+            let spec = self.commands.get(words[0].0).and_then(|c| c.args.get(index - 1));
+            match spec.map(|s| s.kind) {
+                Some(ArgKind::Choice(choices)) => choices.to_vec(),
+                Some(ArgKind::CommandName) => self.commands.keys().cloned().collect(),
+                Some(ArgKind::CvarName) => cvars.iter().map(|c| c.name).collect(),
+                _ => vec![],
+            }
+        };
+        let candidates: Vec<&str> = names.into_iter()
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+
+        let base = &line[..line.len() - prefix.len()];
+        match candidates.len() {
+            0 => Completion { line: line.to_owned(), candidates: vec![] },
+            1 => Completion { line: format!("{}{} ", base, candidates[0]), candidates: vec![] },
+            _ => Completion {
+                line: format!("{}{}", base, common_prefix(&*candidates)),
+                candidates: candidates.iter().map(|c| (*c).to_owned()).collect(),
+            },
+        }
+    }
+
+    /// Look up the command for a command line (without the leading slash), and
+    /// parse its arguments. The handler is returned rather than run, so that
+    /// the caller can release the registry lock first (handlers may need it).
+    pub fn prepare(&self, line: &str) -> Result<Option<(Handler, Args)>> {
+        let words = split_words(line);
+        let (name, _) = match words.first() {
+            Some(&w) => w,
+            None => return Ok(None),
+        };
+        let command = match self.commands.get(name) {
+            Some(c) => c,
+            None => return Err(ErrorKind::Command(
+                format!("Unknown command: {} (try /help)", name)).into()),
+        };
+        let args = command.parse_args(&words[1..])?;
+        Ok(Some((command.handler, args)))
+    }
+}
+
+pub fn register_builtins(registry: &mut CommandRegistry) {
+    registry.register(Command::new(
+        "help", vec![ArgSpec::optional("command", ArgKind::CommandName)],
+        "List commands, or describe one", help));
+    registry.register(Command::new(
+        "quit", vec![], "Exit the client", quit));
+    registry.register(Command::new(
+        "say", vec![ArgSpec::required("text", ArgKind::Rest)],
+        "Say something in chat", say));
+    registry.register(Command::new(
+        "tp", vec![ArgSpec::required("x", ArgKind::Float),
+                   ArgSpec::required("z", ArgKind::Float)],
+        "Teleport to a position on the terrain", tp));
+    registry.register(Command::new(
+        "exec", vec![ArgSpec::required("file", ArgKind::Rest)],
+        "Run the commands in a file", exec));
+}
+
+fn help(state: &State, args: &Args) -> Result<Option<String>> {
+    let commands = state.commands.read().unwrap();
+    if args.len() == 1 {
+        let name = args.word(0).trim_start_matches('/');
+        match commands.get(name) {
+            Some(c) => Ok(Some(format!("/{}\n  {}", c.usage(), c.help))),
+            None => Err(ErrorKind::Command(format!("Unknown command: {}", name)).into()),
+        }
+    } else {
+        let lines: Vec<String> = commands.iter()
+            .map(|c| format!("/{} - {}", c.usage(), c.help))
+            .collect();
+        Ok(Some(lines.join("\n")))
+    }
+}
+
+fn quit(state: &State, _args: &Args) -> Result<Option<String>> {
+    // This starts the shutdown sequence
+    state.terminating.store(true, Ordering::Relaxed);
+    Ok(None)
+}
+
+fn say(state: &State, args: &Args) -> Result<Option<String>> {
+    state.say(args.word(0));
+    Ok(None)
+}
+
+fn exec(state: &State, args: &Args) -> Result<Option<String>> {
+    state.run_script(Path::new(args.word(0)))?;
+    Ok(None)
+}
+
+fn tp(state: &State, args: &Args) -> Result<Option<String>> {
+    state.teleport(args.float(0), args.float(1));
+    Ok(Some(format!("Teleported to ({}, {})", args.float(0), args.float(1))))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Command, ArgSpec, ArgKind, Args, split_words};
+    use errors::*;
+    use state::State;
+
+    fn nothing(_state: &State, _args: &Args) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    #[test]
+    fn test_parse_float_args() {
+        let command = Command::new(
+            "tp", vec![ArgSpec::required("x", ArgKind::Float),
+                       ArgSpec::required("z", ArgKind::Float)],
+            "", nothing);
+
+        let args = command.parse_args(&split_words("1.5 -2")).unwrap();
+        assert_eq!(args.float(0), 1.5);
+        assert_eq!(args.float(1), -2.0);
+
+        for line in &["nan 0", "0 NaN", "inf inf", "-inf 0", "0 infinity", "1e39 0", "x 0"] {
+            assert!(command.parse_args(&split_words(line)).is_err(), "{} parsed", line);
+        }
+    }
+}
@@ -8,6 +8,7 @@ use std::io::Read;
 use std::net::SocketAddr;
 use std::str::FromStr;
 use std::fmt;
+use std::collections::BTreeMap;
 use toml;
 
 use logger::CodeLogLevel;
@@ -90,6 +91,7 @@ impl fmt::Debug for NetworkConfig {
 #[inline] fn default_width() -> u32 { 1280 }
 #[inline] fn default_height() -> u32 { 720 }
 #[inline] fn default_screen_number() -> usize { 0 }
+#[inline] fn default_layout_file() -> PathBuf { PathBuf::from("./window_layout.toml") }
 
 #[derive(Clone, Deserialize)]
 pub struct WindowConfig {
@@ -101,6 +103,8 @@ pub struct WindowConfig {
     pub height: u32,
     #[serde(default = "default_screen_number")]
     pub screen_number: usize,
+    #[serde(default = "default_layout_file")]
+    pub layout_file: PathBuf,
 }
 
 impl Default for WindowConfig {
@@ -110,6 +114,7 @@ impl Default for WindowConfig {
             screen_number: default_screen_number(),
             width: default_width(),
             height: default_height(),
+            layout_file: default_layout_file(),
         }
     }
 }
@@ -122,6 +127,163 @@ impl fmt::Debug for WindowConfig {
             writeln!(f, "    height: {}", self.height)?;
         }
         writeln!(f, "    screen_number: {}", self.screen_number)?;
+        writeln!(f, "    layout_file: {}", self.layout_file.display())?;
+        Ok(())
+    }
+}
+
+//--------------------------------------------------
+
+#[inline] fn default_tick_rate() -> u32 { 60 }
+#[inline] fn default_max_catch_up() -> u32 { 10 }
+
+#[derive(Clone, Deserialize)]
+pub struct SimulationConfig {
+    /// Simulation ticks per second
+    #[serde(default = "default_tick_rate")]
+    pub tick_rate: u32,
+    /// The most ticks we run at once to catch up after a stall. Beyond that,
+    /// the simulation falls behind real time rather than trying to keep up.
+    #[serde(default = "default_max_catch_up")]
+    pub max_catch_up: u32,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> SimulationConfig {
+        SimulationConfig {
+            tick_rate: default_tick_rate(),
+            max_catch_up: default_max_catch_up(),
+        }
+    }
+}
+
+impl fmt::Debug for SimulationConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "    tick_rate: {}", self.tick_rate)?;
+        writeln!(f, "    max_catch_up: {}", self.max_catch_up)?;
+        Ok(())
+    }
+}
+
+//--------------------------------------------------
+
+#[inline] fn default_mouse_sensitivity() -> f32 { 0.15 }
+#[inline] fn default_mouse_invert_y() -> bool { false }
+
+#[derive(Clone, Deserialize)]
+pub struct MouseConfig {
+    /// Degrees of turn per count of mouse movement, in mouse-look
+    #[serde(default = "default_mouse_sensitivity")]
+    pub sensitivity: f32,
+    #[serde(default = "default_mouse_invert_y")]
+    pub invert_y: bool,
+}
+
+impl Default for MouseConfig {
+    fn default() -> MouseConfig {
+        MouseConfig {
+            sensitivity: default_mouse_sensitivity(),
+            invert_y: default_mouse_invert_y(),
+        }
+    }
+}
+
+impl fmt::Debug for MouseConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "    sensitivity: {}", self.sensitivity)?;
+        writeln!(f, "    invert_y: {}", self.invert_y)?;
+        Ok(())
+    }
+}
+
+//--------------------------------------------------
+
+#[inline] fn default_chat_log() -> bool { true }
+#[inline] fn default_chat_log_directory() -> PathBuf { PathBuf::from("./chatlogs") }
+#[inline] fn default_chat_reload_lines() -> usize { 10 }
+#[inline] fn default_chat_margin() -> i32 { 6 }
+#[inline] fn default_chat_width() -> i32 { 470 }
+#[inline] fn default_chat_width_fraction() -> f32 { 0.0 }
+#[inline] fn default_chat_height() -> i32 { 150 }
+#[inline] fn default_chat_height_fraction() -> f32 { 0.0 }
+#[inline] fn default_chat_line_height() -> u8 { 15 }
+#[inline] fn default_chat_line_drop() -> i32 { 5 }
+#[inline] fn default_chat_fade_after() -> f32 { 20.0 }
+#[inline] fn default_chat_fade_duration() -> f32 { 2.0 }
+#[inline] fn default_chat_fade_min_alpha() -> u8 { 48 }
+#[inline] fn default_chat_filter_file() -> PathBuf { PathBuf::from("./chat_filters.toml") }
+
+#[derive(Clone, Deserialize)]
+pub struct ChatConfig {
+    #[serde(default = "default_chat_log")]
+    pub log: bool,
+    #[serde(default = "default_chat_log_directory")]
+    pub log_directory: PathBuf,
+    #[serde(default = "default_chat_reload_lines")]
+    pub reload_lines: usize,
+    #[serde(default = "default_chat_margin")]
+    pub margin: i32,
+    #[serde(default = "default_chat_width")]
+    pub width: i32,
+    #[serde(default = "default_chat_width_fraction")]
+    pub width_fraction: f32,
+    #[serde(default = "default_chat_height")]
+    pub height: i32,
+    #[serde(default = "default_chat_height_fraction")]
+    pub height_fraction: f32,
+    #[serde(default = "default_chat_line_height")]
+    pub line_height: u8,
+    #[serde(default = "default_chat_line_drop")]
+    pub line_drop: i32,
+    #[serde(default = "default_chat_fade_after")]
+    pub fade_after: f32,
+    #[serde(default = "default_chat_fade_duration")]
+    pub fade_duration: f32,
+    #[serde(default = "default_chat_fade_min_alpha")]
+    pub fade_min_alpha: u8,
+    #[serde(default = "default_chat_filter_file")]
+    pub filter_file: PathBuf,
+}
+
+impl Default for ChatConfig {
+    fn default() -> ChatConfig {
+        ChatConfig {
+            log: default_chat_log(),
+            log_directory: default_chat_log_directory(),
+            reload_lines: default_chat_reload_lines(),
+            margin: default_chat_margin(),
+            width: default_chat_width(),
+            width_fraction: default_chat_width_fraction(),
+            height: default_chat_height(),
+            height_fraction: default_chat_height_fraction(),
+            line_height: default_chat_line_height(),
+            line_drop: default_chat_line_drop(),
+            fade_after: default_chat_fade_after(),
+            fade_duration: default_chat_fade_duration(),
+            fade_min_alpha: default_chat_fade_min_alpha(),
+            filter_file: default_chat_filter_file(),
+        }
+    }
+}
+
+impl fmt::Debug for ChatConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "    log: {}", self.log)?;
+        if self.log {
+            writeln!(f, "    log_directory: {}", self.log_directory.display())?;
+        }
+        writeln!(f, "    reload_lines: {}", self.reload_lines)?;
+        writeln!(f, "    margin: {}", self.margin)?;
+        writeln!(f, "    width: {} + {}%", self.width, self.width_fraction * 100.0)?;
+        writeln!(f, "    height: {} + {}%", self.height, self.height_fraction * 100.0)?;
+        writeln!(f, "    line_height: {}", self.line_height)?;
+        writeln!(f, "    line_drop: {}", self.line_drop)?;
+        if self.fade_after > 0.0 {
+            writeln!(f, "    fade_after: {}s", self.fade_after)?;
+            writeln!(f, "    fade_duration: {}s", self.fade_duration)?;
+            writeln!(f, "    fade_min_alpha: {}", self.fade_min_alpha)?;
+        }
+        writeln!(f, "    filter_file: {}", self.filter_file.display())?;
         Ok(())
     }
 }
@@ -132,6 +294,7 @@ impl fmt::Debug for WindowConfig {
     if cfg!(debug_assertions) { CodeLogLevel::Debug }
     else { CodeLogLevel::Warn }
 }
+#[inline] fn default_code_log_console_level() -> CodeLogLevel { CodeLogLevel::Warn }
 #[inline] fn default_code_log_fileline() -> bool { true }
 #[inline] fn default_code_log_detailed_errors() -> bool { false }
 
@@ -139,6 +302,8 @@ impl fmt::Debug for WindowConfig {
 pub struct Config {
     #[serde(default = "default_code_log_level")]
     pub code_log_level: CodeLogLevel,
+    #[serde(default = "default_code_log_console_level")]
+    pub code_log_console_level: CodeLogLevel,
     #[serde(default = "default_code_log_fileline")]
     pub code_log_fileline: bool,
     #[serde(default = "default_code_log_detailed_errors")]
@@ -149,17 +314,41 @@ pub struct Config {
     pub graphics: GraphicsConfig,
     #[serde(default)]
     pub network: NetworkConfig,
+    #[serde(default)]
+    pub mouse: MouseConfig,
+    #[serde(default)]
+    pub simulation: SimulationConfig,
+    #[serde(default)]
+    pub chat: ChatConfig,
+    /// Initial values of console variables (see cvar.rs), by name
+    #[serde(default)]
+    pub cvars: BTreeMap<String, toml::Value>,
+    /// A file of console commands to run at startup. Defaults to autoexec.cfg
+    /// in the same directory as the config file.
+    #[serde(default)]
+    pub autoexec: Option<PathBuf>,
+    /// Key bindings, as key = action. These change the default bindings
+    /// (see bindings.rs), and a key bound to "" is unbound.
+    #[serde(default)]
+    pub bindings: BTreeMap<String, String>,
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
             code_log_level: default_code_log_level(),
+            code_log_console_level: default_code_log_console_level(),
             code_log_fileline: default_code_log_fileline(),
             code_log_detailed_errors: default_code_log_detailed_errors(),
             window: Default::default(),
             graphics: Default::default(),
             network: Default::default(),
+            mouse: Default::default(),
+            simulation: Default::default(),
+            chat: Default::default(),
+            cvars: BTreeMap::new(),
+            autoexec: None,
+            bindings: BTreeMap::new(),
         }
     }
 }
@@ -167,6 +356,7 @@ impl Default for Config {
 impl fmt::Debug for Config {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "  code_log_level: {:?}", self.code_log_level)?;
+        writeln!(f, "  code_log_console_level: {:?}", self.code_log_console_level)?;
         writeln!(f, "  code_log_fileline: {}", self.code_log_fileline)?;
         writeln!(f, "  code_log_detailed_errors: {}", self.code_log_detailed_errors)?;
         writeln!(f, "  window:")?;
@@ -175,6 +365,21 @@ impl fmt::Debug for Config {
         write!(f, "{:?}", self.graphics)?;
         writeln!(f, "  network:")?;
         write!(f, "{:?}", self.network)?;
+        writeln!(f, "  mouse:")?;
+        write!(f, "{:?}", self.mouse)?;
+        writeln!(f, "  simulation:")?;
+        write!(f, "{:?}", self.simulation)?;
+        writeln!(f, "  chat:")?;
+        write!(f, "{:?}", self.chat)?;
+        writeln!(f, "  autoexec: {:?}", self.autoexec)?;
+        writeln!(f, "  cvars:")?;
+        for (name, value) in &self.cvars {
+            writeln!(f, "    {} = {}", name, value)?;
+        }
+        writeln!(f, "  bindings:")?;
+        for (key, action) in &self.bindings {
+            writeln!(f, "    {} = {:?}", key, action)?;
+        }
         Ok(())
     }
 }
@@ -201,12 +406,21 @@ impl Config {
     {
         let path = Config::get_path();
 
-        if ! path.is_file() {
+        let mut config = if ! path.is_file() {
             // Just use default config
-            return Ok(Default::default());
+            Default::default()
+        } else {
+            Config::from_file( path.clone() )?
+        };
+
+        if config.autoexec.is_none() {
+            config.autoexec = Some(path.with_file_name("autoexec.cfg"));
         }
 
-        let config = Config::from_file( path )?;
+        // Chat lines are laid out by their height, so they need one
+        if config.chat.line_height == 0 {
+            return Err(ErrorKind::General("chat.line_height must be at least 1".to_owned()).into());
+        }
 
         Ok(config)
     }
@@ -0,0 +1,457 @@
+
+use std::collections::BTreeMap;
+use std::collections::btree_map::Values;
+use std::fmt;
+use siege_math::{Angle, Vec4};
+use errors::*;
+use state::{State, RenderParams};
+use camera::{DEFAULT_FOV, MIN_FOV, MAX_FOV,
+             MIN_ORBIT_DISTANCE, DEFAULT_ORBIT_DISTANCE, MAX_ORBIT_DISTANCE,
+             MIN_FREE_SPEED, DEFAULT_FREE_SPEED, MAX_FREE_SPEED,
+             MAX_SMOOTHING, DEFAULT_POSITION_SMOOTHING, DEFAULT_ORIENTATION_SMOOTHING};
+use command::{CommandRegistry, Command, ArgSpec, ArgKind, Args};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CvarKind {
+    /// A number within an inclusive range
+    Float { min: f32, max: f32 },
+    /// A direction, normalized when it is set
+    Direction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CvarValue {
+    Float(f32),
+    Direction([f32; 3]),
+}
+
+impl CvarValue {
+    /// Panics if the value is not a Float (the kind of a cvar never changes,
+    /// so this is a programming error)
+    pub fn float(&self) -> f32 {
+        match *self {
+            CvarValue::Float(f) => f,
+            ref v => panic!("Cvar value is not a float: {:?}", v),
+        }
+    }
+
+    /// Panics if the value is not a Direction
+    pub fn direction(&self) -> Vec4<f32> {
+        match *self {
+            CvarValue::Direction(d) => Vec4::new(d[0], d[1], d[2], 0.0),
+            ref v => panic!("Cvar value is not a direction: {:?}", v),
+        }
+    }
+}
+
+impl fmt::Display for CvarValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CvarValue::Float(v) => write!(f, "{}", v),
+            CvarValue::Direction(d) => write!(f, "{} {} {}", d[0], d[1], d[2]),
+        }
+    }
+}
+
+fn direction_value(v: &Vec4<f32>) -> CvarValue {
+    CvarValue::Direction([v.x, v.y, v.z])
+}
+
+fn cvar_error(message: String) -> Error {
+    ErrorKind::Command(message).into()
+}
+
+/// A console variable. The value does not live here, it lives wherever the
+/// rest of the program uses it, and is reached through the getter and setter.
+pub struct Cvar {
+    pub name: &'static str,
+    pub kind: CvarKind,
+    pub default: CvarValue,
+    pub help: &'static str,
+    getter: fn(&State) -> CvarValue,
+    setter: fn(&State, CvarValue),
+}
+
+impl Cvar {
+    pub fn new(name: &'static str, kind: CvarKind, default: CvarValue, help: &'static str,
+               getter: fn(&State) -> CvarValue, setter: fn(&State, CvarValue)) -> Cvar
+    {
+        Cvar {
+            name: name,
+            kind: kind,
+            default: default,
+            help: help,
+            getter: getter,
+            setter: setter,
+        }
+    }
+
+    pub fn get(&self, state: &State) -> CvarValue {
+        (self.getter)(state)
+    }
+
+    /// Parse a value for this cvar, checking that it is in range.
+    /// Directions may be written as "x y z", "x, y, z" or "[x, y, z]".
+    pub fn parse(&self, text: &str) -> Result<CvarValue> {
+        match self.kind {
+            CvarKind::Float { min, max } => {
+                let v: f32 = text.trim().parse().map_err(
+                    |_| cvar_error(format!("{} must be a number", self.name)))?;
+                // ("nan" and "inf" parse, but are not numbers we can use)
+                if !v.is_finite() {
+                    return Err(cvar_error(format!("{} must be a number", self.name)));
+                }
+                if v < min || v > max {
+                    return Err(cvar_error(
+                        format!("{} must be between {} and {}", self.name, min, max)));
+                }
+                Ok(CvarValue::Float(v))
+            },
+            CvarKind::Direction => {
+                let usage = || cvar_error(format!("{} must be three numbers: x y z", self.name));
+                let parts = text.trim().trim_start_matches('[').trim_end_matches(']')
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.parse::<f32>())
+                    .collect::<::std::result::Result<Vec<f32>, _>>()
+                    .map_err(|_| usage())?;
+                if parts.len() != 3 {
+                    return Err(usage());
+                }
+                let length = (parts[0]*parts[0] + parts[1]*parts[1] + parts[2]*parts[2]).sqrt();
+                if !length.is_finite() || length < 1e-6 {
+                    return Err(cvar_error(format!("{} must not be zero", self.name)));
+                }
+                Ok(CvarValue::Direction(
+                    [parts[0] / length, parts[1] / length, parts[2] / length]))
+            },
+        }
+    }
+
+    pub fn set(&self, state: &State, text: &str) -> Result<CvarValue> {
+        let value = self.parse(text)?;
+        (self.setter)(state, value);
+        Ok(value)
+    }
+
+    /// Add to a Float cvar, stopping at the ends of its range
+    pub fn adjust(&self, state: &State, delta: f32) -> Result<CvarValue> {
+        let (min, max) = match self.kind {
+            CvarKind::Float { min, max } => (min, max),
+            _ => return Err(cvar_error(format!("{} is not a number", self.name))),
+        };
+        let value = CvarValue::Float((self.get(state).float() + delta).max(min).min(max));
+        (self.setter)(state, value);
+        Ok(value)
+    }
+
+    pub fn reset(&self, state: &State) -> CvarValue {
+        (self.setter)(state, self.default);
+        self.default
+    }
+}
+
+pub struct CvarRegistry {
+    cvars: BTreeMap<&'static str, Cvar>,
+}
+
+impl CvarRegistry {
+    pub fn new() -> CvarRegistry {
+        CvarRegistry {
+            cvars: BTreeMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, cvar: Cvar) {
+        if self.cvars.contains_key(cvar.name) {
+            warn!("Cvar {} registered twice", cvar.name);
+        }
+        self.cvars.insert(cvar.name, cvar);
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Cvar> {
+        self.cvars.get(name).ok_or_else(
+            || cvar_error(format!("Unknown variable: {} (try /cvars)", name)))
+    }
+
+    pub fn iter(&self) -> Values<&'static str, Cvar> {
+        self.cvars.values()
+    }
+}
+
+pub fn register_builtins(registry: &mut CvarRegistry) {
+    let rp = RenderParams::default();
+    registry.register(Cvar::new(
+        "fov", CvarKind::Float { min: MIN_FOV.to_degrees(), max: MAX_FOV.to_degrees() },
+        CvarValue::Float(DEFAULT_FOV.to_degrees()),
+        "Horizontal field of view, in degrees", get_fov, set_fov));
+    registry.register(Cvar::new(
+        "orbit_distance", CvarKind::Float { min: MIN_ORBIT_DISTANCE, max: MAX_ORBIT_DISTANCE },
+        CvarValue::Float(DEFAULT_ORBIT_DISTANCE),
+        "How far behind the avatar the third-person camera is, in meters",
+        get_orbit_distance, set_orbit_distance));
+    registry.register(Cvar::new(
+        "orbit_yaw", CvarKind::Float { min: -180.0, max: 180.0 },
+        CvarValue::Float(0.0),
+        "Third-person camera angle around the avatar, in degrees (positive is to the left)",
+        get_orbit_yaw, set_orbit_yaw));
+    registry.register(Cvar::new(
+        "orbit_pitch", CvarKind::Float { min: -80.0, max: 80.0 },
+        CvarValue::Float(0.0),
+        "Third-person camera angle above the avatar, in degrees",
+        get_orbit_pitch, set_orbit_pitch));
+    registry.register(Cvar::new(
+        "camera_position_smoothing", CvarKind::Float { min: 0.0, max: MAX_SMOOTHING },
+        CvarValue::Float(DEFAULT_POSITION_SMOOTHING),
+        "How long the camera takes to catch up with the avatar, in seconds (0 is instant)",
+        get_position_smoothing, set_position_smoothing));
+    registry.register(Cvar::new(
+        "camera_orientation_smoothing", CvarKind::Float { min: 0.0, max: MAX_SMOOTHING },
+        CvarValue::Float(DEFAULT_ORIENTATION_SMOOTHING),
+        "How long the third-person camera takes to turn with the avatar, in seconds",
+        get_orientation_smoothing, set_orientation_smoothing));
+    registry.register(Cvar::new(
+        "free_speed", CvarKind::Float { min: MIN_FREE_SPEED, max: MAX_FREE_SPEED },
+        CvarValue::Float(DEFAULT_FREE_SPEED),
+        "How fast the free camera flies, in meters per second",
+        get_free_speed, set_free_speed));
+    registry.register(Cvar::new(
+        "bloom_strength", CvarKind::Float { min: 0.0, max: 1.0 },
+        CvarValue::Float(rp.bloom_strength),
+        "How strongly bright areas bleed into their surroundings",
+        get_bloom_strength, set_bloom_strength));
+    registry.register(Cvar::new(
+        "bloom_cliff", CvarKind::Float { min: 0.0, max: 1.0 },
+        CvarValue::Float(rp.bloom_cliff),
+        "How bright an area must be before it blooms", get_bloom_cliff, set_bloom_cliff));
+    registry.register(Cvar::new(
+        "blur_level", CvarKind::Float { min: 0.0, max: 1.0 },
+        CvarValue::Float(rp.blur_level),
+        "How much the whole scene is blurred", get_blur_level, set_blur_level));
+    registry.register(Cvar::new(
+        "ambient", CvarKind::Float { min: 0.0, max: 1.0 },
+        CvarValue::Float(rp.ambient),
+        "Ambient light level", get_ambient, set_ambient));
+    registry.register(Cvar::new(
+        "white_level", CvarKind::Float { min: 0.01, max: 10.0 },
+        CvarValue::Float(rp.white_level),
+        "Irradiance that is shown as full white", get_white_level, set_white_level));
+    registry.register(Cvar::new(
+        "light_dir", CvarKind::Direction,
+        direction_value(&rp.light_dir),
+        "Direction of the main light (world space)", get_light_dir, set_light_dir));
+    registry.register(Cvar::new(
+        "light_dir_2", CvarKind::Direction,
+        direction_value(&rp.light_dir_2),
+        "Direction of the second light (world space)", get_light_dir_2, set_light_dir_2));
+}
+
+fn get_fov(state: &State) -> CvarValue {
+    CvarValue::Float(state.camera.read().unwrap().fovx.as_radians().to_degrees())
+}
+
+fn set_fov(state: &State, value: CvarValue) {
+    state.camera.write().unwrap().set_fovx(Angle::<f32>::from_degrees(value.float()));
+}
+
+fn get_orbit_distance(state: &State) -> CvarValue {
+    CvarValue::Float(state.camera.read().unwrap().orbit_distance)
+}
+
+fn set_orbit_distance(state: &State, value: CvarValue) {
+    state.camera.write().unwrap().orbit_distance = value.float();
+}
+
+fn get_orbit_yaw(state: &State) -> CvarValue {
+    CvarValue::Float(state.camera.read().unwrap().orbit_yaw.to_degrees())
+}
+
+fn set_orbit_yaw(state: &State, value: CvarValue) {
+    state.camera.write().unwrap().orbit_yaw = value.float().to_radians();
+}
+
+fn get_orbit_pitch(state: &State) -> CvarValue {
+    CvarValue::Float(state.camera.read().unwrap().orbit_pitch.to_degrees())
+}
+
+fn set_orbit_pitch(state: &State, value: CvarValue) {
+    state.camera.write().unwrap().orbit_pitch = value.float().to_radians();
+}
+
+fn get_position_smoothing(state: &State) -> CvarValue {
+    CvarValue::Float(state.camera.read().unwrap().position_smoothing)
+}
+
+fn set_position_smoothing(state: &State, value: CvarValue) {
+    state.camera.write().unwrap().position_smoothing = value.float();
+}
+
+fn get_orientation_smoothing(state: &State) -> CvarValue {
+    CvarValue::Float(state.camera.read().unwrap().orientation_smoothing)
+}
+
+fn set_orientation_smoothing(state: &State, value: CvarValue) {
+    state.camera.write().unwrap().orientation_smoothing = value.float();
+}
+
+fn get_free_speed(state: &State) -> CvarValue {
+    CvarValue::Float(state.camera.read().unwrap().free_speed)
+}
+
+fn set_free_speed(state: &State, value: CvarValue) {
+    state.camera.write().unwrap().free_speed = value.float();
+}
+
+fn get_bloom_strength(state: &State) -> CvarValue {
+    CvarValue::Float(state.render_params.read().unwrap().bloom_strength)
+}
+
+fn set_bloom_strength(state: &State, value: CvarValue) {
+    state.render_params.write().unwrap().bloom_strength = value.float();
+}
+
+fn get_bloom_cliff(state: &State) -> CvarValue {
+    CvarValue::Float(state.render_params.read().unwrap().bloom_cliff)
+}
+
+fn set_bloom_cliff(state: &State, value: CvarValue) {
+    state.render_params.write().unwrap().bloom_cliff = value.float();
+}
+
+fn get_blur_level(state: &State) -> CvarValue {
+    CvarValue::Float(state.render_params.read().unwrap().blur_level)
+}
+
+fn set_blur_level(state: &State, value: CvarValue) {
+    state.render_params.write().unwrap().blur_level = value.float();
+}
+
+fn get_ambient(state: &State) -> CvarValue {
+    CvarValue::Float(state.render_params.read().unwrap().ambient)
+}
+
+fn set_ambient(state: &State, value: CvarValue) {
+    state.render_params.write().unwrap().ambient = value.float();
+}
+
+fn get_white_level(state: &State) -> CvarValue {
+    CvarValue::Float(state.render_params.read().unwrap().white_level)
+}
+
+fn set_white_level(state: &State, value: CvarValue) {
+    state.render_params.write().unwrap().white_level = value.float();
+}
+
+fn get_light_dir(state: &State) -> CvarValue {
+    direction_value(&state.render_params.read().unwrap().light_dir)
+}
+
+fn set_light_dir(state: &State, value: CvarValue) {
+    state.render_params.write().unwrap().light_dir = value.direction();
+}
+
+fn get_light_dir_2(state: &State) -> CvarValue {
+    direction_value(&state.render_params.read().unwrap().light_dir_2)
+}
+
+fn set_light_dir_2(state: &State, value: CvarValue) {
+    state.render_params.write().unwrap().light_dir_2 = value.direction();
+}
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(Command::new(
+        "set", vec![ArgSpec::required("name", ArgKind::CvarName),
+                    ArgSpec::required("value", ArgKind::Rest)],
+        "Set a variable", set));
+    registry.register(Command::new(
+        "get", vec![ArgSpec::required("name", ArgKind::CvarName)],
+        "Show the value of a variable", get));
+    registry.register(Command::new(
+        "cvars", vec![], "List variables and their values", cvars));
+    registry.register(Command::new(
+        "reset", vec![ArgSpec::optional("name", ArgKind::CvarName)],
+        "Reset a variable (or all of them) to its default", reset));
+    registry.register(Command::new(
+        "adjust", vec![ArgSpec::required("name", ArgKind::CvarName),
+                       ArgSpec::required("delta", ArgKind::Float)],
+        "Add to a numeric variable", adjust));
+
+    // Shortcuts for the variables that had their own commands before there
+    // were cvars
+    registry.register(Command::new(
+        "fov", vec![ArgSpec::optional("degrees", ArgKind::Float)],
+        "Show or set the horizontal field of view (the fov variable)", fov));
+    registry.register(Command::new(
+        "bloom", vec![ArgSpec::optional("strength", ArgKind::Float)],
+        "Show or set the bloom strength (the bloom_strength variable)", bloom));
+    registry.register(Command::new(
+        "bloom_cliff", vec![ArgSpec::optional("cliff", ArgKind::Float)],
+        "Show or set the bloom cliff (the bloom_cliff variable)", bloom_cliff));
+    registry.register(Command::new(
+        "blur", vec![ArgSpec::optional("level", ArgKind::Float)],
+        "Show or set the blur level (the blur_level variable)", blur));
+}
+
+fn set(state: &State, args: &Args) -> Result<Option<String>> {
+    let cvar = state.cvars.get(args.word(0))?;
+    let value = cvar.set(state, args.word(1))?;
+    Ok(Some(format!("{} = {}", cvar.name, value)))
+}
+
+fn get(state: &State, args: &Args) -> Result<Option<String>> {
+    let cvar = state.cvars.get(args.word(0))?;
+    Ok(Some(format!("{} = {}", cvar.name, cvar.get(state))))
+}
+
+fn cvars(state: &State, _args: &Args) -> Result<Option<String>> {
+    let lines: Vec<String> = state.cvars.iter()
+        .map(|c| format!("{} = {} - {}", c.name, c.get(state), c.help))
+        .collect();
+    Ok(Some(lines.join("\n")))
+}
+
+fn reset(state: &State, args: &Args) -> Result<Option<String>> {
+    if args.len() == 1 {
+        let cvar = state.cvars.get(args.word(0))?;
+        let value = cvar.reset(state);
+        Ok(Some(format!("{} = {}", cvar.name, value)))
+    } else {
+        for cvar in state.cvars.iter() {
+            cvar.reset(state);
+        }
+        Ok(Some("All variables reset to their defaults".to_owned()))
+    }
+}
+
+fn adjust(state: &State, args: &Args) -> Result<Option<String>> {
+    let cvar = state.cvars.get(args.word(0))?;
+    let value = cvar.adjust(state, args.float(1))?;
+    Ok(Some(format!("{} = {}", cvar.name, value)))
+}
+
+// Show a cvar, or set it if a value was given
+fn shortcut(state: &State, name: &str, args: &Args) -> Result<Option<String>> {
+    let cvar = state.cvars.get(name)?;
+    let value = if args.len() == 1 {
+        cvar.set(state, &*args.float(0).to_string())?
+    } else {
+        cvar.get(state)
+    };
+    Ok(Some(format!("{} = {}", cvar.name, value)))
+}
+
+fn fov(state: &State, args: &Args) -> Result<Option<String>> {
+    shortcut(state, "fov", args)
+}
+
+fn bloom(state: &State, args: &Args) -> Result<Option<String>> {
+    shortcut(state, "bloom_strength", args)
+}
+
+fn bloom_cliff(state: &State, args: &Args) -> Result<Option<String>> {
+    shortcut(state, "bloom_cliff", args)
+}
+
+fn blur(state: &State, args: &Args) -> Result<Option<String>> {
+    shortcut(state, "blur_level", args)
+}
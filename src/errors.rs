@@ -16,6 +16,7 @@ error_chain! {
         Io(::std::io::Error);
         Addr(::std::net::AddrParseError);
         TomlDe(::toml::de::Error);
+        TomlSer(::toml::ser::Error);
         SetLogger(::log::SetLoggerError);
         WinitCreation(::winit::CreationError);
         Dacite(::dacite::core::Error);
@@ -29,5 +30,9 @@ error_chain! {
             description("General Error"),
             display("General Error: '{}'", s),
         }
+        Command(s: String) {
+            description("Command Error"),
+            display("{}", s),
+        }
     }
 }
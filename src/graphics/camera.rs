@@ -8,7 +8,6 @@ use siege_render::{Renderer, HostVisibleBuffer, Lifetime, Plugin,
                    Params, Stats};
 use errors::*;
 use State;
-use super::{LIGHT_DIR, LIGHT_DIR_2};
 
 /*
  How far can one see on a planet?
@@ -55,20 +54,12 @@ impl CameraUniforms {
     pub fn update(&mut self,
                   state: &State)
     {
+        // Interpolated between simulation ticks (and already above the terrain)
+        let position = From::from(state.update_camera().position);
         let (fovx, view_matrix, extent) = {
             let camera = state.camera.read().unwrap();
             (camera.fovx.as_radians(), camera.view_matrix, camera.extent)
         };
-        let position = {
-            let avatar = state.avatar.read().unwrap();
-            let mut placement = avatar.get_current_placement();
-            // Place above terrain
-            placement.position.0.y = state.terrain.get_y(
-                placement.position.0.x,
-                placement.position.0.z
-            );
-            From::from(placement.position)
-        };
 
         self.camera_position_wspace = position;
         self.fovx = fovx;
@@ -218,25 +209,19 @@ impl Plugin for CameraGfx {
 
     fn update(&mut self, params: &mut Params, _stats: &Stats) -> ::siege_render::Result<bool> {
 
-        let (bloom_strength, bloom_cliff, blur_level) = {
-            let rp = self.state.render_params.read().unwrap();
-            (
-                rp.bloom_strength,
-                rp.bloom_cliff,
-                rp.blur_level
-            )
-        };
-
         // Update the uniforms
         self.camera_uniforms.update(&self.state);
 
-        // Update the renderer
-        params.dlight_directions[0] = &self.camera_uniforms.view_matrix * &LIGHT_DIR;
-        params.dlight_directions[1] = &self.camera_uniforms.view_matrix * &LIGHT_DIR_2;
+        // Update the renderer (these may have been changed from the console)
+        let rp = self.state.render_params.read().unwrap();
+        params.dlight_directions[0] = &self.camera_uniforms.view_matrix * &rp.light_dir;
+        params.dlight_directions[1] = &self.camera_uniforms.view_matrix * &rp.light_dir_2;
         params.inv_projection = self.inv_projection();
-        params.bloom_strength = bloom_strength;
-        params.bloom_cliff = bloom_cliff;
-        params.blur_level = blur_level;
+        params.bloom_strength = rp.bloom_strength;
+        params.bloom_cliff = rp.bloom_cliff;
+        params.blur_level = rp.blur_level;
+        params.ambient = rp.ambient;
+        params.white_level = rp.white_level;
 
         Ok(false)
     }
@@ -7,11 +7,12 @@ use dacite::core::{DescriptorSetLayout, DescriptorSet,
                   CommandBuffer, PipelineLayout, Extent2D,
                    PrimitiveTopology, CullModeFlags, FrontFace};
 use siege_mesh::GrayboxVertex;
-use siege_math::{Vec4, Mat4, Point3};
+use siege_math::{Vec3, Vec4, Mat4, Point3};
 use siege_render::{Renderer, HostVisibleBuffer, Lifetime, VulkanMesh,
                    Pass, VulkanVertex, BlendMode, Plugin,
                    Params, Stats, PipelineSetup};
 use errors::*;
+use camera::Bounds;
 use State;
 
 #[repr(C)]
@@ -269,36 +270,56 @@ impl Plugin for GrayboxGfx {
 
         let mut current_vis = BitVec::from_elem(self.grayboxes.len(), true);
 
+        let mut solids = Vec::new();
+
         for (i, ref mut graybox) in self.grayboxes.iter_mut().enumerate() {
             // Determine visibility
             {
-                if let Some(ref bs) = graybox.mesh.bounding_sphere {
-                    let camera = self.state.camera.read().unwrap();
+                let bounds = if let Some(ref bs) = graybox.mesh.bounding_sphere {
                     let v4: Vec4<f32> = From::from(bs.0);
                     let v4: Vec4<f32> = &graybox.model_matrix * &v4;
                     let p3: Point3<f32> = From::from(v4);
-                    graybox.visible = camera.might_be_visible(&p3, bs.1);
+                    Some(Bounds::Sphere(p3, bs.1))
                 }
                 else if let Some(ref bc) = graybox.mesh.bounding_cuboid {
-                    let camera = self.state.camera.read().unwrap();
-                    graybox.visible = false;
+                    // The cuboid is a box in mesh space, so it is an oriented
+                    // box in world space
+                    let mut min = Vec3::new(::std::f32::MAX, ::std::f32::MAX, ::std::f32::MAX);
+                    let mut max = Vec3::new(::std::f32::MIN, ::std::f32::MIN, ::std::f32::MIN);
                     for corner in 0..8 {
                         let v4: Vec4<f32> = From::from(bc[corner]);
-                        let v4: Vec4<f32> = &graybox.model_matrix * &v4;
-                        let p3: Point3<f32> = From::from(v4);
-                        if camera.might_be_visible(&p3, 0.0) {
-                            graybox.visible = true;
-                            break;
-                        }
+                        min = Vec3::new(min.x.min(v4.x), min.y.min(v4.y), min.z.min(v4.z));
+                        max = Vec3::new(max.x.max(v4.x), max.y.max(v4.y), max.z.max(v4.z));
                     }
+                    let center = Vec4::new((min.x + max.x) / 2.0, (min.y + max.y) / 2.0,
+                                           (min.z + max.z) / 2.0, 1.0);
+                    let model_matrix = graybox.model_matrix;
+                    let center: Point3<f32> = From::from(&model_matrix * &center);
+                    let half_axis = |x: f32, y: f32, z: f32| -> Vec3<f32> {
+                        (&model_matrix * &Vec4::new(x, y, z, 0.0)).truncate_w()
+                    };
+                    Some(Bounds::Cuboid(center, [half_axis((max.x - min.x) / 2.0, 0.0, 0.0),
+                                                 half_axis(0.0, (max.y - min.y) / 2.0, 0.0),
+                                                 half_axis(0.0, 0.0, (max.z - min.z) / 2.0)]))
                 }
                 else {
-                    graybox.visible = true;
-                }
+                    None
+                };
+                graybox.visible = match bounds {
+                    Some(ref bounds) => {
+                        let camera = self.state.camera.read().unwrap();
+                        bounds.might_be_visible(&camera)
+                    },
+                    None => true,
+                };
                 current_vis.set(i, graybox.visible);
+                solids.extend(bounds);
             }
         }
 
+        // For picking (see labels.rs)
+        *self.state.solids.write().unwrap() = solids;
+
         if current_vis != self.visibility {
             self.visibility = current_vis;
             //debug!("Re-recording grayboxes: {:?}", self.visibility);
@@ -37,19 +37,6 @@ pub struct GraphicsSystem {
     state: Arc<State>,
 }
 
-const LIGHT_DIR: Vec4<f32> = Vec4 {
-    x:  0.5773502691896258,
-    y: -0.5773502691896258,
-    z:  0.5773502691896258,
-    w: 0.0,
-};
-const LIGHT_DIR_2: Vec4<f32> = Vec4 {
-    x: -0.8017837257372732,
-    y: -0.2672612419124244,
-    z: -0.5345224838248488,
-    w: 0.0,
-};
-
 impl GraphicsSystem {
     pub fn new(config: Arc<Config>, state: Arc<State>, window: Arc<Window>)
                -> Result<GraphicsSystem>
@@ -129,27 +116,19 @@ impl GraphicsSystem {
         let text_gfx = TextGfx::new(&mut renderer, state.clone())?;
 
         let params = {
-            let (bloom_strength, bloom_cliff, blur_level) = {
-                let rp = state.render_params.read().unwrap();
-                (
-                    rp.bloom_strength,
-                    rp.bloom_cliff,
-                    rp.blur_level
-                )
-            };
-
+            let rp = state.render_params.read().unwrap();
             Params {
                 inv_projection: camera.inv_projection(),
-                dlight_directions: [LIGHT_DIR, LIGHT_DIR_2],
+                dlight_directions: [rp.light_dir, rp.light_dir_2],
                 dlight_irradiances: [
                     Vec4::new(1.0, 1.0, 1.0, 0.0),
                     Vec4::new(2.0, 1.8, 1.7, 0.0),
                 ],
-                bloom_strength: bloom_strength,
-                bloom_cliff: bloom_cliff,
-                blur_level: blur_level,
-                ambient: 0.08,
-                white_level: 1.0,
+                bloom_strength: rp.bloom_strength,
+                bloom_cliff: rp.bloom_cliff,
+                blur_level: rp.blur_level,
+                ambient: rp.ambient,
+                white_level: rp.white_level,
                 tonemapper: config.graphics.renderer.tonemapper,
             }
         };
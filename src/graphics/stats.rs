@@ -49,6 +49,7 @@ impl StatsGfx {
             [0.0, 0.0, 0.0, 0.5], 0.8
         );
         let win_handle = state.ui.add_element(UiElement::Window(win.clone()), None).unwrap();
+        UiWindow::make_movable(&state.ui, win_handle, "stats");
 
         const LINEHEIGHT: u8 = 14;
         let mut y: i32 = 22;
@@ -18,7 +18,7 @@ use siege_render::{Renderer, Pass, BlendMode, Stats,
                    Plugin, Params, ImageWrap, Lifetime, HostVisibleBuffer,
                    PipelineSetup};
 use state::State;
-use ui::{Ui, AbsRect, UiElement, TextLine};
+use ui::{Ui, AbsRect, UiElement, Point, TextStyle, TextSpan};
 use errors::*;
 
 // We have 6 vertices per glyph (triangle strip wouldn't let us separate glyphs)
@@ -280,7 +280,8 @@ impl TextGfx {
         })
     }
 
-    fn generate_vertices(&self, line: &TextLine, vport: &AbsRect, alpha: f32)
+    fn generate_vertices(&self, ui_coordinates: &Point, lineheight: u8, line_alpha: u8,
+                         spans: &[TextSpan], vport: &AbsRect, alpha: f32)
                          -> Vec<GlyphVertex>
     {
         let mut vertices: Vec<GlyphVertex> = Vec::new();
@@ -293,14 +294,20 @@ impl TextGfx {
         vport.y = 0.0;
 
         // Interpret coordinates relative to the viewport
-        let (mut cursor_x, cursor_y) = line.ui_coordinates.absolute(&vport);
+        let (mut cursor_x, cursor_y) = ui_coordinates.absolute(&vport);
 
         let atlas: &FontAtlas = &self.font_data.atlas;
 
-        let scale = line.lineheight as f32 / atlas.line_height;
+        let scale = lineheight as f32 / atlas.line_height;
         let margin = atlas.margin * scale;
 
-        for ch in line.text.chars() {
+        // Each glyph carries the props of the span it belongs to
+        let glyphs = spans.iter().flat_map(|span| {
+            let props = encode_props_plus(&span.style, line_alpha, margin, alpha);
+            span.text.chars().map(move |ch| (ch, props))
+        });
+
+        for (ch, props) in glyphs {
             let atlas_cinfo = match atlas.map.get(&ch) {
                 Some(cinfo) => cinfo,
                 None => {
@@ -355,8 +362,6 @@ impl TextGfx {
                 h: 2.0 * screen_px.h / vport.height,
             };
 
-            let props = encode_props_plus(&line, margin, alpha);
-
             let upper_left = GlyphVertex {
                 encoded_screen: encode_screen(screen_gl.x, screen_gl.y),
                 encoded_uv: encode_uv(uv.x, uv.y),
@@ -405,13 +410,18 @@ impl TextGfx {
             let element = &(*nodeguard).element;
 
             // This pipeline only renders text
-            let textline = match element {
-                &UiElement::Text(ref t) => t,
+            let groupvertices = match element {
+                &UiElement::Text(ref t) => {
+                    let spans = [TextSpan { style: t.style(), text: t.text.clone() }];
+                    self.generate_vertices(&t.ui_coordinates, t.lineheight, t.alpha,
+                                           &spans, &nodeinfo.rect, nodeinfo.alpha)
+                },
+                &UiElement::Styled(ref s) => {
+                    self.generate_vertices(&s.ui_coordinates, s.lineheight, s.alpha,
+                                           &*s.spans, &nodeinfo.rect, nodeinfo.alpha)
+                },
                 _ => continue // we only render text here
             };
-
-            let groupvertices = self.generate_vertices(textline, &nodeinfo.rect,
-                                                       nodeinfo.alpha);
             let grouplen = groupvertices.len();
             self.vertex_buffer.extend(groupvertices);
             self.vertex_groups.push(GroupData {
@@ -552,12 +562,12 @@ impl Plugin for TextGfx {
 }
 
 #[inline]
-fn encode_props_plus(line: &TextLine, margin: f32, alpha: f32) -> u32 {
-    let alpha_u: u32 = ((((line.alpha as f32) / 255.0) * alpha) * 255.0) as u32;
+fn encode_props_plus(style: &TextStyle, line_alpha: u8, margin: f32, alpha: f32) -> u32 {
+    let alpha_u: u32 = ((((line_alpha as f32) / 255.0) * alpha) * 255.0) as u32;
     let mut output: u32 = alpha_u;
-    output |= (line.font as u32) << 8;
-    output |= (line.color as u32) << 10;
-    if let Some(ocolor) = line.outline {
+    output |= (style.font as u32) << 8;
+    output |= (style.color as u32) << 10;
+    if let Some(ocolor) = style.outline {
         output |= (ocolor as u32) << 13;
         output |= 0x00010000;
     }
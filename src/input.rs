@@ -1,35 +1,65 @@
 
 use errors::*;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 use state::State;
+use config::Config;
+use chat;
+use bindings::{Action, KeyChord, key_char};
+use camera::CameraMode;
+use ui::{Handle, UiEvent};
 use winit::{Window, EventsLoop, Event, WindowEvent, WindowId, KeyboardInput,
-            DeviceId, ModifiersState};
+            DeviceId, ModifiersState, ElementState, VirtualKeyCode as Key,
+            DeviceEvent, MouseButton, MouseScrollDelta, CursorState};
 use siege_net::packets::ShutdownPacket;
 use siege_example_net::packet::GamePacket;
-use siege_math::Angle;
-use siege_plugin_avatar_simple::MoveDirection;
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Mode {
-    Normal,
-    Chat,
-    Command,
-}
+
+// Scrolling by pixels (touchpads) is converted to lines at this rate
+const PIXELS_PER_LINE: f32 = 20.0;
 
 pub struct InputSystem {
-    mode: Mode,
+    // Whether the key that moved the focus typed a character, which the newly
+    // focused node should not get. It is whatever character comes next, since
+    // the modifiers held can change which one it is.
+    skip_char: bool,
+    // Keys that are down, and the action each one started
+    pressed: HashMap<Key, Action>,
+    // Keys that are down, whose press went to the focused ui node instead
+    ui_pressed: HashSet<Key>,
+    // Mouse-look is on while the right button is held, or while toggled on
+    look_held: bool,
+    look_toggled: bool,
+    cursor_grabbed: bool,
+    // Radians of turn per count of mouse movement
+    mouse_sensitivity: f32,
+    mouse_invert_y: bool,
+    // Where the cursor is within the window, in pixels
+    cursor: (f32, f32),
+    // The ui node under the cursor
+    hovered: Option<Handle>,
+    // The ui node that took a mouse button press gets the mouse until release
+    captured: Option<(Handle, MouseButton)>,
     state: Arc<State>,
-    #[allow(dead_code)]
     window: Arc<Window>
 }
 
 impl InputSystem {
-    pub fn new(state: Arc<State>, window: Arc<Window>)
+    pub fn new(state: Arc<State>, window: Arc<Window>, config: &Config)
                -> InputSystem
     {
         InputSystem {
-            mode: Mode::Normal,
+            skip_char: false,
+            pressed: HashMap::new(),
+            ui_pressed: HashSet::new(),
+            look_held: false,
+            look_toggled: false,
+            cursor_grabbed: false,
+            mouse_sensitivity: config.mouse.sensitivity.to_radians(),
+            mouse_invert_y: config.mouse.invert_y,
+            cursor: (0.0, 0.0),
+            hovered: None,
+            captured: None,
             state: state,
             window: window,
         }
@@ -71,7 +101,7 @@ impl InputSystem {
                 return Ok(());
             }
 
-            // Update state
+            // Update the ui (the simulation runs on its own clock, see sim.rs)
             self.state.periodic_update();
 
             // Throttle
@@ -87,6 +117,8 @@ impl InputSystem {
         match e {
             Event::WindowEvent { window_id, event } =>
                 self.handle_window_event(event, window_id),
+            Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } =>
+                self.handle_mouse_motion(delta.0, delta.1),
             Event::DeviceEvent { .. } => {},
             Event::Awakened => {},
             Event::Suspended(_) => {},
@@ -96,10 +128,14 @@ impl InputSystem {
     pub fn handle_window_event(&mut self, e: WindowEvent, _window_id: WindowId)
     {
         match e {
-            WindowEvent::Resized(_,_) => {
+            WindowEvent::Resized(width, height) => {
                 // This affects vulkan;  We record this event so we can rebuild the
                 // swapchain.
                 self.state.resized.store(true, Ordering::Relaxed);
+                self.state.ui.set_screen_size(width, height);
+
+                // The chat window may now fit a different number of lines
+                self.state.chat.write().unwrap().resize(&self.state.ui);
             },
             WindowEvent::Closed => {
                 // This starts the shutdown sequence
@@ -107,45 +143,59 @@ impl InputSystem {
             },
             WindowEvent::ReceivedCharacter(ch) => {
                 // This handles characters, which is very useful for unicode input,
-                // rather than key up/down events.  We use it for chat/command modes.
-                if self.mode == Mode::Chat || self.mode == Mode::Command {
-                    self.handle_character(ch);
+                // rather than key up/down events.  They go to the focused ui node
+                // (e.g. the chat window, while typing).
+                if ::std::mem::replace(&mut self.skip_char, false) {
+                    return;
+                }
+                if let Some(focused) = self.state.ui.focused() {
+                    self.state.ui.dispatch(focused, &UiEvent::Character(ch));
                 }
             },
             WindowEvent::KeyboardInput { device_id, input } => {
-                // This handles up/down events.  We use it for normal mode.
-                if self.mode == Mode::Normal {
-                    self.handle_keyboard(device_id, input);
-                }
+                // This handles up/down events.  They go to the focused ui node
+                // first, then to the bindings.
+                self.handle_keyboard(device_id, input);
             }
             WindowEvent::CursorMoved { device_id, position, modifiers } => {
                 // x and y are pixel positions within the window
                 self.handle_mouse(device_id, position.0, position.1, modifiers);
             }
+            WindowEvent::CursorLeft { .. } => {
+                self.set_hovered(None);
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.handle_mouse_button(state, button);
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.handle_mouse_wheel(delta);
+            }
+            WindowEvent::Focused(false) => {
+                // Give the cursor back when another window takes over
+                self.look_held = false;
+                self.look_toggled = false;
+                self.update_mouse_look();
+                // and finish any drag, since we will not see the button go up
+                if let Some((handle, button)) = self.captured.take() {
+                    let (x, y) = self.cursor;
+                    let event = UiEvent::MouseUp { button: button, x: x, y: y };
+                    self.state.ui.dispatch(handle, &event);
+                }
+                // Key releases go to the other window too, so let go of everything
+                // now rather than walking forever
+                self.release_keys();
+            }
+            WindowEvent::Focused(true) => {
+                // Anything still marked as held was pressed before we lost track.
+                // Keys that really are held will be pressed again as they repeat.
+                self.release_keys();
+            }
             _ => { }
         }
     }
 
-    pub fn handle_character(&mut self, ch: char)
-    {
-        if ch=='\u{001b}' { // Escape
-            self.mode = Mode::Normal;
-            // ignore command/chat buffers.
-        }
-        else if ch=='\u{000d}' { // CR (Enter)
-            self.mode = Mode::Normal;
-            // FIXME: handle command or chat buffer
-        }
-        else {
-            debug!("Character: {} = {}", ch, ch.escape_unicode());
-        }
-    }
-
     pub fn handle_keyboard(&mut self, _device_id: DeviceId, input: KeyboardInput)
     {
-        use winit::ElementState;
-        use winit::VirtualKeyCode as Key;
-
         // We only handle keys with virtual keycodes (currently)
         // (NumLock, PrintScreen, ScrollLock and Pause do not have codes, but are often
         //  used by the O.S. and we really ought not overload their meaning)
@@ -154,79 +204,296 @@ impl InputSystem {
             Some(k) => k
         };
 
-        // We trigger some special keys on release
         if let ElementState::Released = input.state {
-            match (input.modifiers.shift, input.modifiers.ctrl, input.modifiers.alt,
-                   input.modifiers.logo, key)
-            {
-                // "LOGO-ESCAPE" quits, when the key is released.
-                (_,_,_,true,Key::Escape) => {
-                    // This starts the shutdown sequence
-                    self.state.terminating.store(true, Ordering::Relaxed);
-                },
-                (_,false,false,false,Key::W) | (_,false,false,false,Key::Up) =>
-                    self.state.movement_cmd(MoveDirection::Forward, false),
-                (_,false,false,false,Key::S) | (_,false,false,false,Key::Down) =>
-                    self.state.movement_cmd(MoveDirection::Backward, false),
-                (_,false,false,false,Key::A) | (_,false,false,false,Key::Left) =>
-                    self.state.movement_cmd(MoveDirection::YawLeft, false),
-                (_,false,false,false,Key::D) | (_,false,false,false,Key::Right) =>
-                    self.state.movement_cmd(MoveDirection::YawRight, false),
-                (_,false,false,false,Key::Q) =>
-                    self.state.movement_cmd(MoveDirection::StrafeLeft, false),
-                (_,false,false,false,Key::E) =>
-                    self.state.movement_cmd(MoveDirection::StrafeRight, false),
-                (_,false,false,false,Key::PageUp) =>
-                    self.state.movement_cmd(MoveDirection::PitchUp, false),
-                (_,false,false,false,Key::PageDown) =>
-                    self.state.movement_cmd(MoveDirection::PitchDown, false),
-                _ => {}
+            // Release whatever the key did when it was pressed, even if the
+            // modifiers have changed since
+            if let Some(action) = self.pressed.remove(&key) {
+                self.end_action(action);
+            } else if self.ui_pressed.remove(&key) {
+                if let Some(focused) = self.state.ui.focused() {
+                    self.state.ui.dispatch(focused, &UiEvent::KeyUp { key: key });
+                }
             }
         } else {
-            match (input.modifiers.shift, input.modifiers.ctrl, input.modifiers.alt,
-                   input.modifiers.logo, key)
+            if !self.pressed.contains_key(&key) && self.handle_focus_key(key, input.modifiers) {
+                self.ui_pressed.insert(key);
+                return;
+            }
+            if let Some(action) = self.pressed.get(&key) {
+                // Key repeat. Only /adjust steps again; everything else
+                // stays as it is until the key is released.
+                match *action {
+                    Action::Run(ref command) if action.repeats() =>
+                        self.state.run_command(&*command),
+                    _ => {},
+                }
+                return;
+            }
+            let action = {
+                let bindings = self.state.bindings.read().unwrap();
+                match bindings.lookup(&KeyChord::new(key, input.modifiers)) {
+                    Some(action) => action.clone(),
+                    None => {
+                        trace!("KEY: {:?}", key);
+                        return;
+                    }
+                }
+            };
+            // Recorded movement is in control during playback (but the free
+            // camera can still fly)
+            if action.move_direction().is_some()
+                && self.state.replay.lock().unwrap().is_playing()
+                && self.state.camera.read().unwrap().free.is_none()
             {
-                (_,false,false,false,Key::Grave) => self.mode = Mode::Chat,
-                (_,false,false,false,Key::Slash) => self.mode = Mode::Command,
-                (_,false,false,false,Key::W) | (_,false,false,false,Key::Up) =>
-                    self.state.movement_cmd(MoveDirection::Forward, true),
-                (_,false,false,false,Key::S) | (_,false,false,false,Key::Down) =>
-                    self.state.movement_cmd(MoveDirection::Backward, true),
-                (_,false,false,false,Key::A) | (_,false,false,false,Key::Left) =>
-                    self.state.movement_cmd(MoveDirection::YawLeft, true),
-                (_,false,false,false,Key::D) | (_,false,false,false,Key::Right) =>
-                    self.state.movement_cmd(MoveDirection::YawRight, true),
-                (_,false,false,false,Key::Q) =>
-                    self.state.movement_cmd(MoveDirection::StrafeLeft, true),
-                (_,false,false,false,Key::E) =>
-                    self.state.movement_cmd(MoveDirection::StrafeRight, true),
-                (_,false,false,false,Key::PageUp) =>
-                    self.state.movement_cmd(MoveDirection::PitchUp, true),
-                (_,false,false,false,Key::PageDown) =>
-                    self.state.movement_cmd(MoveDirection::PitchDown, true),
-                (_,false,false,false,Key::F3) =>
-                    self.state.adjust_fovx(Angle::<f32>::from_degrees(0.5)),
-                (_,false,false,false,Key::F4) =>
-                    self.state.adjust_fovx(Angle::<f32>::from_degrees(-0.5)),
-                (_,false,false,false,Key::F7) =>
-                    self.state.adjust_blur_level(false),
-                (_,false,false,false,Key::F8) =>
-                    self.state.adjust_blur_level(true),
-                (_,false,false,false,Key::F9) =>
-                    self.state.adjust_bloom_strength(false),
-                (_,false,false,false,Key::F10) =>
-                    self.state.adjust_bloom_strength(true),
-                (_,false,false,false,Key::F11) =>
-                    self.state.adjust_bloom_cliff(false),
-                (_,false,false,false,Key::F12) =>
-                    self.state.adjust_bloom_cliff(true),
-                (_,_,_,_,key) => trace!("KEY: {:?}", key),
-            }
-        }
-    }
-
-    pub fn handle_mouse(&self, _device_id: DeviceId, _x: f64, _y: f64,
+                return;
+            }
+            self.start_action(&action, key);
+            self.pressed.insert(key, action);
+        }
+    }
+
+    // Forget which keys are down, stopping any movement they started. Other
+    // actions are dropped without doing what their release would do.
+    fn release_keys(&mut self)
+    {
+        let pressed: Vec<(Key, Action)> = self.pressed.drain().collect();
+        for (key, action) in pressed {
+            if action.move_direction().is_some() {
+                debug!("Releasing {:?} ({})", key, action);
+                self.end_action(action);
+            }
+        }
+        let focused = self.state.ui.focused();
+        for key in self.ui_pressed.drain() {
+            if let Some(handle) = focused {
+                self.state.ui.dispatch(handle, &UiEvent::KeyUp { key: key });
+            }
+        }
+    }
+
+    // Keys go to the focused ui node first. Tab and Shift-Tab move the focus,
+    // and Escape drops it. Returns false if the key is for the bindings.
+    fn handle_focus_key(&mut self, key: Key, modifiers: ModifiersState) -> bool
+    {
+        let focused = self.state.ui.focused();
+        if let Some(handle) = focused {
+            let event = UiEvent::KeyDown { key: key, modifiers: modifiers };
+            if self.state.ui.dispatch(handle, &event).is_some() {
+                return true;
+            }
+        }
+        let plain = !(modifiers.ctrl || modifiers.alt || modifiers.logo);
+        match key {
+            Key::Tab if plain && self.state.ui.focus_next(modifiers.shift) => {
+                self.skip_char = true;
+                true
+            },
+            Key::Escape if plain && !modifiers.shift && focused.is_some() => {
+                self.state.ui.set_focus(None);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    // Do what a key does when it is pressed
+    fn start_action(&mut self, action: &Action, key: Key)
+    {
+        if let Some(direction) = action.move_direction() {
+            if let Some(ref mut free) = self.state.camera.write().unwrap().free {
+                free.move_cmd(direction, true);
+                return;
+            }
+            self.state.movement_cmd(action.clone(), true);
+            return;
+        }
+        match *action {
+            Action::Chat => {
+                chat::start_typing(&self.state, false);
+                self.skip_char = key_char(key).is_some();
+            },
+            Action::Command => {
+                chat::start_typing(&self.state, true);
+                self.skip_char = key_char(key).is_some();
+            },
+            Action::MouseLook => {
+                self.look_toggled = !self.look_toggled;
+                self.update_mouse_look();
+            },
+            Action::ToggleCamera => {
+                self.state.camera.write().unwrap().toggle_mode();
+            },
+            Action::FreeCamera => {
+                // Stop whatever the held keys are moving, so that they do not
+                // keep moving it after the other one takes over
+                self.release_keys();
+                if self.state.toggle_free_camera() {
+                    info!("Free camera (the avatar stays put)");
+                } else {
+                    info!("Camera follows the avatar");
+                }
+            },
+            Action::Run(ref command) => self.state.run_command(&*command),
+            _ => {},
+        }
+    }
+
+    // Do what a key does when it is released
+    fn end_action(&mut self, action: Action)
+    {
+        if let Some(direction) = action.move_direction() {
+            if let Some(ref mut free) = self.state.camera.write().unwrap().free {
+                free.move_cmd(direction, false);
+                return;
+            }
+            self.state.movement_cmd(action, false);
+            return;
+        }
+        if let Action::Quit = action {
+            // This starts the shutdown sequence (on release, so the key up
+            // does not go to some other window)
+            self.state.terminating.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // Grab (and hide) the cursor while mouse-look is on, and release it after
+    fn update_mouse_look(&mut self)
+    {
+        let grab = self.look_held || self.look_toggled;
+        if grab == self.cursor_grabbed {
+            return;
+        }
+        let cursor_state = if grab { CursorState::Grab } else { CursorState::Normal };
+        match self.window.set_cursor_state(cursor_state) {
+            Ok(()) => self.cursor_grabbed = grab,
+            Err(e) => warn!("Unable to set cursor state: {}", e),
+        }
+        if self.cursor_grabbed {
+            // A hidden cursor is not pointing at anything
+            self.set_hovered(None);
+        }
+    }
+
+    // Raw mouse movement, in counts (not pixels, and not limited by the window)
+    fn handle_mouse_motion(&mut self, dx: f64, dy: f64)
+    {
+        if !self.cursor_grabbed {
+            return;
+        }
+        // Moving right turns right, moving forward (up) looks up
+        let yaw = -(dx as f32) * self.mouse_sensitivity;
+        let mut pitch = -(dy as f32) * self.mouse_sensitivity;
+        if self.mouse_invert_y {
+            pitch = -pitch;
+        }
+        if let Some(ref mut free) = self.state.camera.write().unwrap().free {
+            free.look(yaw, pitch);
+            return;
+        }
+        if self.state.replay.lock().unwrap().is_playing() {
+            return;
+        }
+        self.state.look(yaw, pitch);
+    }
+
+    pub fn handle_mouse(&mut self, _device_id: DeviceId, x: f64, y: f64,
                         _modifers: ModifiersState)
     {
+        self.cursor = (x as f32, y as f32);
+        if self.cursor_grabbed {
+            return;
+        }
+        let event = UiEvent::MouseMove { x: x as f32, y: y as f32 };
+        if let Some((handle, _)) = self.captured {
+            self.state.ui.dispatch(handle, &event);
+            return;
+        }
+        let target = self.hit_test();
+        self.set_hovered(target);
+        if let Some(handle) = target {
+            self.state.ui.dispatch(handle, &event);
+        }
+    }
+
+    // The ui node under the cursor, if the cursor is showing
+    fn hit_test(&self) -> Option<Handle>
+    {
+        if self.cursor_grabbed {
+            return None;
+        }
+        self.state.ui.hit_test(self.cursor.0, self.cursor.1)
+    }
+
+    fn set_hovered(&mut self, target: Option<Handle>)
+    {
+        if target == self.hovered {
+            return;
+        }
+        if let Some(old) = self.hovered.take() {
+            self.state.ui.dispatch(old, &UiEvent::HoverLeave);
+        }
+        if let Some(new) = target {
+            self.state.ui.dispatch(new, &UiEvent::HoverEnter);
+        }
+        self.hovered = target;
+    }
+
+    // Buttons go to the ui first. The right button does mouse-look when the
+    // ui does not want it.
+    fn handle_mouse_button(&mut self, state: ElementState, button: MouseButton)
+    {
+        let (x, y) = self.cursor;
+        if state == ElementState::Pressed {
+            let target = self.hit_test();
+            if button == MouseButton::Left {
+                // Clicking a node that can take the focus gives it the focus;
+                // clicking anything else takes the focus away
+                self.state.ui.focus_at(target);
+            }
+            let event = UiEvent::MouseDown { button: button, x: x, y: y };
+            let handler = target.and_then(|h| self.state.ui.dispatch(h, &event));
+            if let Some(handle) = handler {
+                if self.captured.is_none() {
+                    self.captured = Some((handle, button));
+                }
+                return;
+            }
+            if button == MouseButton::Right {
+                self.look_held = true;
+                self.update_mouse_look();
+            }
+        } else {
+            let event = UiEvent::MouseUp { button: button, x: x, y: y };
+            match self.captured {
+                Some((handle, b)) if b == button => {
+                    self.captured = None;
+                    self.state.ui.dispatch(handle, &event);
+                },
+                _ => if let Some(handle) = self.hit_test() {
+                    self.state.ui.dispatch(handle, &event);
+                },
+            }
+            if button == MouseButton::Right {
+                self.look_held = false;
+                self.update_mouse_look();
+            }
+        }
+    }
+
+    fn handle_mouse_wheel(&mut self, delta: MouseScrollDelta)
+    {
+        let (dx, dy) = match delta {
+            MouseScrollDelta::LineDelta(dx, dy) => (dx, dy),
+            MouseScrollDelta::PixelDelta(dx, dy) => (dx / PIXELS_PER_LINE, dy / PIXELS_PER_LINE),
+        };
+        let event = UiEvent::Wheel { dx: dx, dy: dy };
+        if self.hit_test().and_then(|h| self.state.ui.dispatch(h, &event)).is_some() {
+            return;
+        }
+        // Otherwise scrolling moves the third-person camera in and out
+        let mut camera = self.state.camera.write().unwrap();
+        if camera.mode == CameraMode::ThirdPerson {
+            camera.zoom_orbit(dy);
+        }
     }
 }
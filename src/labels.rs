@@ -0,0 +1,145 @@
+
+use siege_math::Point3;
+use errors::*;
+use state::State;
+use camera::Camera;
+use ui::{Ui, Handle, UiElement, TextLine, TextColor, Font, Point, Coord};
+use command::{CommandRegistry, Command, ArgSpec, ArgKind, Args};
+
+const LINEHEIGHT: u8 = 20;
+
+// How far ahead to put a label when the view does not meet the ground
+const LABEL_DISTANCE: f32 = 10.0;
+
+// How far away to look for the ground or a graybox
+const MAX_PICK_DISTANCE: f32 = 1000.0;
+
+struct WorldLabel {
+    position: Point3<f32>,
+    handle: Handle,
+    // Half the width of the text, so that it can be centered
+    half_width: f32,
+    // Where it is on the screen, if it is shown
+    shown_at: Option<(i32, i32)>,
+}
+
+/// Text lines anchored to points in the world, kept over them on the screen
+pub struct WorldLabels {
+    labels: Vec<WorldLabel>,
+    // Text lines of cleared labels, to reuse (the ui cannot remove nodes)
+    spare: Vec<Handle>,
+}
+
+impl WorldLabels {
+    pub fn new() -> WorldLabels {
+        WorldLabels {
+            labels: Vec::new(),
+            spare: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, ui: &Ui, position: Point3<f32>, text: &str) {
+        let line = TextLine {
+            ui_coordinates: Point { x: Coord::near(0.0, 0), y: Coord::near(0.0, 0) },
+            lineheight: LINEHEIGHT,
+            color: TextColor::White,
+            outline: Some(TextColor::Black),
+            font: Font::Main,
+            alpha: 0, // until it is placed
+            text: text.to_owned(),
+        };
+        let half_width = ui.get_pixel_length(&line) / 2.0;
+        let handle = match self.spare.pop() {
+            Some(handle) => {
+                ui.set_text(handle, line.text);
+                handle
+            },
+            None => match ui.add_element(UiElement::Text(line), None) {
+                Some(handle) => handle,
+                None => return,
+            },
+        };
+        self.labels.push(WorldLabel {
+            position: position,
+            handle: handle,
+            half_width: half_width,
+            shown_at: None,
+        });
+    }
+
+    pub fn clear(&mut self, ui: &Ui) -> usize {
+        let count = self.labels.len();
+        for label in self.labels.drain(..) {
+            ui.set_alpha(label.handle, 0);
+            self.spare.push(label.handle);
+        }
+        count
+    }
+
+    /// Move the labels to where their points are on the screen, hiding those
+    /// that are out of view. Call this after the camera moves.
+    pub fn update(&mut self, ui: &Ui, camera: &Camera) {
+        for label in &mut self.labels {
+            // Centered over the point
+            let at = camera.project(&label.position).map(
+                |(x, y, _)| ((x - label.half_width).round() as i32, y.round() as i32));
+            if at == label.shown_at {
+                continue;
+            }
+            match at {
+                Some((x, y)) => {
+                    ui.set_position(label.handle,
+                                    Point { x: Coord::near(0.0, x), y: Coord::near(0.0, y) });
+                    if label.shown_at.is_none() {
+                        ui.set_alpha(label.handle, 255);
+                    }
+                },
+                None => {
+                    ui.set_alpha(label.handle, 0);
+                },
+            }
+            label.shown_at = at;
+        }
+    }
+}
+
+const LABEL_ACTIONS: &'static [&'static str] = &["add", "clear"];
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(Command::new(
+        "label", vec![ArgSpec::required("action", ArgKind::Choice(LABEL_ACTIONS)),
+                      ArgSpec::optional("text", ArgKind::Rest)],
+        "Put a label where the middle of the view meets the ground or a graybox, or clear \
+         them all",
+        label));
+}
+
+fn label(state: &State, args: &Args) -> Result<Option<String>> {
+    if args.word(0) == "clear" {
+        let count = state.labels.lock().unwrap().clear(&state.ui);
+        return Ok(Some(format!("Cleared {} labels", count)));
+    }
+    if args.len() < 2 {
+        return Err(ErrorKind::Command("Usage: label add <text>".to_owned()).into());
+    }
+
+    // (the camera is locked before the labels everywhere else too)
+    let position = {
+        let camera = state.camera.read().unwrap();
+        let ray = camera.screen_ray(camera.extent.width as f32 / 2.0,
+                                    camera.extent.height as f32 / 2.0);
+        let ground = state.terrain.ray_hit(&ray, MAX_PICK_DISTANCE);
+        let nearest = state.solids.read().unwrap().iter()
+            .filter_map(|bounds| bounds.ray_hit(&ray))
+            .filter(|distance| *distance <= MAX_PICK_DISTANCE)
+            .fold(ground, |nearest, distance| match nearest {
+                Some(n) if n <= distance => Some(n),
+                _ => Some(distance),
+            });
+        let distance = nearest.unwrap_or(LABEL_DISTANCE);
+        ray.at(distance)
+    };
+    state.labels.lock().unwrap().add(&state.ui, position, args.word(1));
+    let p = position.0;
+    Ok(Some(format!("Label added at ({:.1}, {:.1}, {:.1})", p.x, p.y, p.z)))
+}
@@ -1,8 +1,29 @@
 
+use std::cmp;
+use std::sync::Arc;
+use crossbeam::sync::MsQueue;
 use errors::*;
 use log::{Record, Level, Metadata};
 use config::Config;
 
+/// Log records with this target are always shown in the console (the chat window),
+/// whatever the configured levels, e.g. `info!(target: CONSOLE_TARGET, "Connected")`.
+/// They must be at least Info: debug and trace records are filtered out before
+/// they reach the logger, unless a configured level lets them through.
+pub const CONSOLE_TARGET: &'static str = "siege_console";
+
+/// A log record on its way to the console
+#[derive(Debug, Clone)]
+pub struct ConsoleRecord {
+    pub level: Level,
+    pub text: String,
+}
+
+/// Records are queued for the console rather than being written into the chat
+/// window directly. The code that logs may already hold the chat lock, and
+/// the logger can be called from any thread.
+pub type ConsoleQueue = Arc<MsQueue<ConsoleRecord>>;
+
 #[derive(Deserialize, Debug, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
 pub enum CodeLogLevel {
@@ -27,25 +48,41 @@ impl CodeLogLevel {
 
 struct SiegeLogger {
     log_level: Level,
+    console_level: Level,
     log_fileline: bool,
+    console: ConsoleQueue,
+}
+
+impl SiegeLogger {
+    fn for_console(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.console_level || metadata.target() == CONSOLE_TARGET
+    }
 }
 
-pub fn init(config: &Config) -> Result<()> {
+/// Start logging. Returns the queue of records for the console, which should be
+/// drained periodically.
+pub fn init(config: &Config) -> Result<ConsoleQueue> {
     let log_level = config.code_log_level.as_log_level();
+    let console_level = config.code_log_console_level.as_log_level();
+    let console: ConsoleQueue = Arc::new(MsQueue::new());
 
     ::log::set_boxed_logger(
         Box::new(SiegeLogger {
             log_level: log_level,
+            console_level: console_level,
             log_fileline: config.code_log_fileline,
+            console: console.clone(),
         })
     )?;
-    ::log::set_max_level(log_level.to_level_filter());
-    Ok(())
+    // Console target records need at least Info to get through
+    let max_level = cmp::max(cmp::max(log_level, console_level), Level::Info);
+    ::log::set_max_level(max_level.to_level_filter());
+    Ok(console)
 }
 
 impl ::log::Log for SiegeLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.log_level
+        (metadata.level() <= self.log_level || self.for_console(metadata))
             && metadata.target().starts_with("siege")
     }
 
@@ -57,6 +94,17 @@ impl ::log::Log for SiegeLogger {
             return;
         }
 
+        if self.for_console(record.metadata()) {
+            self.console.push(ConsoleRecord {
+                level: record.level(),
+                text: format!("{}", record.args()),
+            });
+        }
+
+        if record.level() > self.log_level {
+            return;
+        }
+
         let stderr = &mut ::std::io::stderr();
         let badstderr = "Error writing to stderr";
 
@@ -71,11 +119,6 @@ impl ::log::Log for SiegeLogger {
                 .expect(badstderr);
         }
 
-        // record.target() is currently not used.  If not set in a log macro,
-        // it ends up being the module path.  In the future we could use it to
-        // indicate if logging should go to a the console or to the user via
-        // the graphics.
-
         writeln!(stderr, "{}: {}", record.level(), record.args())
             .expect(badstderr);
     }
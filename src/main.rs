@@ -81,6 +81,10 @@ mod network;
 
 mod camera;
 
+mod camera_path;
+
+mod labels;
+
 mod terrain;
 
 mod ui;
@@ -89,6 +93,16 @@ mod stats;
 
 mod chat;
 
+mod command;
+
+mod cvar;
+
+mod bindings;
+
+mod replay;
+
+mod sim;
+
 // These maximums are due to the size of memory chunks that we define in
 // graphics/memory.rs.  4K resolution is the maximum that we support.
 const MAX_WIDTH: u32 = 3840;
@@ -153,7 +167,7 @@ fn run() -> Result<()> {
     let arc_config = Arc::new(config.clone());
 
     // Start logging
-    logger::init(&arc_config)?;
+    let console = logger::init(&arc_config)?;
 
     info!("siege-example-client starting up.");
 
@@ -161,7 +175,10 @@ fn run() -> Result<()> {
     trace!("Tracing is enabled.");
 
     // Create shared state
-    let arc_state: Arc<State> = Arc::new(state::State::new(&arc_config)?);
+    let arc_state: Arc<State> = Arc::new(state::State::new(&arc_config, console)?);
+
+    // Let the chat window take typing (it needs the shared state)
+    chat::attach_input(&arc_state);
 
     // Setup a custom panic hook (On any panic, we want to set the terminating bool)
     let default_panic_hook = ::std::panic::take_hook();
@@ -181,6 +198,14 @@ fn run() -> Result<()> {
         }
     });
 
+    // Setup the simulation system
+    let mut sim_system = sim::SimSystem::new(arc_state.clone(), arc_config.clone());
+    let sim_guard = thread::spawn(move|| {
+        if let Err(ref e) = sim_system.run() {
+            error_dump(e);
+        }
+    });
+
     // Setup the winit event loop
     let events_loop = EventsLoop::new();
 
@@ -257,13 +282,36 @@ fn run() -> Result<()> {
 
     // Add some chat text
     {
+        use chat::Channel;
         let mut chat = arc_state.chat.write().unwrap();
         let ui = &arc_state.ui;
-        chat.emit_line(ui, "Welcome to the Siege Sample Client");
-        chat.emit_line(ui, " [WIN]+[ESC] exits.");
-        chat.emit_line(ui, " Use WASDQE keys to move. PgUp/PgDown tilts camera.");
-        chat.emit_line(ui, " F3/F4 zoom  F7/F8 blur");
-        chat.emit_line(ui, " F9/F10 bloom strength, F11/F12 bloom cliff");
+        chat.emit_line(ui, Channel::System, "Welcome to the Siege Sample Client");
+        chat.emit_markup(ui, Channel::System, " {gold}[WIN]+[ESC]{/} exits.");
+        chat.emit_markup(ui, Channel::System,
+                         " Use {gold}WASDQE{/} keys to move. {gold}PgUp/PgDown{/} tilts camera.");
+        chat.emit_markup(ui, Channel::System,
+                         " Hold the {gold}right mouse button{/} (or press {gold}F2{/}) to mouse-look.");
+        chat.emit_markup(ui, Channel::System, " {gold}F3/F4{/} zoom  {gold}F7/F8{/} blur");
+        chat.emit_markup(ui, Channel::System,
+                         " {gold}F5{/} third person (scroll to move the camera in and out)");
+        chat.emit_markup(ui, Channel::System,
+                         " {gold}F6{/} free camera, {gold}=/-{/} its speed");
+        chat.emit_markup(ui, Channel::System,
+                         " {gold}F9/F10{/} bloom strength, {gold}F11/F12{/} bloom cliff");
+        chat.emit_markup(ui, Channel::System,
+                         " {gold}`{/} to chat, {gold}/{/} for commands ({gold}/help{/} lists them)");
+    }
+
+    // Run the startup script, now that everything is up
+    if let Some(ref path) = arc_config.autoexec {
+        if path.is_file() {
+            info!("Running startup script {}", path.display());
+            if let Err(e) = arc_state.run_script(path) {
+                warn!("Unable to run startup script {}: {}", path.display(), e);
+            }
+        } else {
+            debug!("No startup script at {}", path.display());
+        }
     }
 
     trace!("All systems go. Main thread waiting for child threads to complete.");
@@ -272,7 +320,7 @@ fn run() -> Result<()> {
 
     // Start handing input
     let mut input_system = input::InputSystem::new(
-        arc_state.clone(), arc_window.clone());
+        arc_state.clone(), arc_window.clone(), &arc_config);
     if let Err(ref e) = input_system.run(events_loop) {
         error_dump(e);
     }
@@ -285,5 +333,9 @@ fn run() -> Result<()> {
     let _ = net_guard.join();
     trace!("Network system thread has completed.");
 
+    // Wait for the simulation system to end
+    let _ = sim_guard.join();
+    trace!("Simulation system thread has completed.");
+
     Ok(())
 }
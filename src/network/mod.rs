@@ -262,8 +262,9 @@ impl NetworkSystem {
 
         // Write a chat message (to help test chat)
         {
+            use chat::Channel;
             let mut chat = self.state.chat.write().unwrap();
-            chat.emit_line(&self.state.ui, "thump...");
+            chat.emit_line(&self.state.ui, Channel::System, "thump...");
         }
 
         Ok(Continue::KeepGoing)
@@ -0,0 +1,304 @@
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind as IoErrorKind, Write};
+use std::path::Path;
+use bincode;
+use siege_math::{Point3, Vec3};
+use errors::*;
+use state::State;
+use camera::copy_placement;
+use bindings::Action;
+use chat::Channel;
+use command::{CommandRegistry, Command, ArgSpec, ArgKind, Args};
+
+// Bump this when the format changes
+const VERSION: u32 = 2;
+
+/// An abstract input event, as recorded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum InputEvent {
+    /// A key bound to a movement action was pressed
+    Start(Action),
+    /// A key bound to a movement action was released
+    End(Action),
+    /// Mouse-look turned the avatar by (yaw, pitch) radians
+    Look(f32, f32),
+    /// The recording was stopped before this tick
+    Stop,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TimedEvent {
+    /// The simulation tick it took effect on, counting from 1 at the first
+    /// tick of the recording
+    tick: u64,
+    event: InputEvent,
+}
+
+/// How the avatar was when recording started, so playback can start there too
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    position: (f32, f32, f32),
+    pitch: f32,
+    yaw: f32,
+    /// How many keys were holding each movement direction (see sim::Motion)
+    held: [u32; 8],
+}
+
+/// Records the input the simulation applies, tick by tick. Everything happens
+/// on the simulation thread, so a recording plays back exactly.
+pub struct Recorder {
+    file: BufWriter<File>,
+    // Ticks since recording started. The header is written at the first one.
+    tick: u64,
+    count: usize,
+}
+
+impl Recorder {
+    pub fn new(path: &Path) -> Result<Recorder> {
+        Ok(Recorder {
+            file: BufWriter::new(File::create(path)?),
+            tick: 0,
+            count: 0,
+        })
+    }
+
+    // Start a simulation tick
+    fn begin_tick(&mut self, state: &State) -> Result<()> {
+        if self.tick == 0 {
+            let header = {
+                let placement = copy_placement(&state.avatar.read().unwrap().placement);
+                let p = placement.position.0;
+                Header {
+                    version: VERSION,
+                    position: (p.x, p.y, p.z),
+                    pitch: placement.pitch,
+                    yaw: placement.yaw,
+                    held: state.held_movement(),
+                }
+            };
+            bincode::serialize_into(&mut self.file, &header)?;
+        }
+        self.tick += 1;
+        Ok(())
+    }
+
+    pub fn record(&mut self, event: InputEvent) -> Result<()> {
+        let timed = TimedEvent {
+            tick: self.tick,
+            event: event,
+        };
+        bincode::serialize_into(&mut self.file, &timed)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Finish the recording. Returns how many events were recorded.
+    pub fn finish(mut self) -> Result<usize> {
+        if self.tick > 0 {
+            // Mark where it stopped (before the next tick), so that playback
+            // stops there too
+            let timed = TimedEvent {
+                tick: self.tick + 1,
+                event: InputEvent::Stop,
+            };
+            bincode::serialize_into(&mut self.file, &timed)?;
+        }
+        self.file.flush()?;
+        Ok(self.count)
+    }
+}
+
+pub struct Playback {
+    header: Header,
+    events: VecDeque<TimedEvent>,
+    // Ticks since playback started. The avatar is put in place at the first one.
+    tick: u64,
+}
+
+impl Playback {
+    /// Load a recording. Playback starts at the next simulation tick.
+    pub fn new(path: &Path) -> Result<Playback> {
+        let mut file = BufReader::new(File::open(path)?);
+        let header: Header = bincode::deserialize_from(&mut file)?;
+        if header.version != VERSION {
+            return Err(ErrorKind::Command(
+                format!("{} is a version {} recording, we play version {}",
+                        path.display(), header.version, VERSION)).into());
+        }
+
+        let mut events = VecDeque::new();
+        loop {
+            match bincode::deserialize_from::<_, TimedEvent>(&mut file) {
+                Ok(event) => events.push_back(event),
+                Err(e) => {
+                    // A recording that was cut short has no stop marker
+                    let eof = match *e {
+                        bincode::ErrorKind::Io(ref io) => io.kind() == IoErrorKind::UnexpectedEof,
+                        _ => false,
+                    };
+                    if eof {
+                        break;
+                    }
+                    return Err(e.into());
+                },
+            }
+        }
+
+        Ok(Playback {
+            header: header,
+            events: events,
+            tick: 0,
+        })
+    }
+
+    /// Start a simulation tick, applying the events recorded on the same tick.
+    /// Returns false when the recording is done.
+    fn play(&mut self, state: &State) -> bool {
+        if self.tick == 0 {
+            let mut avatar = state.avatar.write().unwrap();
+            let (x, y, z) = self.header.position;
+            avatar.placement.position = Point3(Vec3::new(x, y, z));
+            avatar.placement.pitch = self.header.pitch;
+            avatar.placement.yaw = self.header.yaw;
+            state.set_held_movement(self.header.held);
+        }
+        self.tick += 1;
+
+        while self.events.front().map(|e| e.tick <= self.tick).unwrap_or(false) {
+            let timed = self.events.pop_front().unwrap();
+            match timed.event {
+                InputEvent::Start(action) => {
+                    if let Some(direction) = action.move_direction() {
+                        state.apply_movement(direction, true);
+                    }
+                },
+                InputEvent::End(action) => {
+                    if let Some(direction) = action.move_direction() {
+                        state.apply_movement(direction, false);
+                    }
+                },
+                InputEvent::Look(yaw, pitch) => state.apply_look(yaw, pitch),
+                InputEvent::Stop => return false,
+            }
+        }
+        !self.events.is_empty()
+    }
+}
+
+/// Input is either being recorded, played back, or neither
+pub enum Replay {
+    Off,
+    Recording(Recorder),
+    Playing(Playback),
+}
+
+impl Replay {
+    pub fn is_playing(&self) -> bool {
+        match *self {
+            Replay::Playing(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Record an event, if we are recording. A failure stops the recording.
+    pub fn record(&mut self, event: InputEvent) {
+        let failed = match *self {
+            Replay::Recording(ref mut recorder) => match recorder.record(event) {
+                Ok(()) => false,
+                Err(e) => {
+                    warn!("Unable to record input, recording stopped: {}", e);
+                    true
+                }
+            },
+            _ => false,
+        };
+        if failed {
+            *self = Replay::Off;
+        }
+    }
+
+    /// Start a simulation tick: play back the events recorded on it, or count
+    /// it for recording. Call this every simulation tick, before its commands.
+    pub fn update(&mut self, state: &State) {
+        let result = match *self {
+            Replay::Playing(ref mut playback) => Ok(playback.play(state)),
+            Replay::Recording(ref mut recorder) => recorder.begin_tick(state).map(|()| true),
+            Replay::Off => Ok(true),
+        };
+        match result {
+            Ok(true) => {},
+            Ok(false) => {
+                if let Err(e) = self.stop(state) {
+                    warn!("Unable to stop playback: {}", e);
+                }
+                state.chat.write().unwrap().emit_line(
+                    &state.ui, Channel::System, "Playback finished");
+            },
+            Err(e) => {
+                warn!("Unable to record input, recording stopped: {}", e);
+                *self = Replay::Off;
+            },
+        }
+    }
+
+    // Stop recording or playing back, describing what was stopped
+    fn stop(&mut self, state: &State) -> Result<String> {
+        match ::std::mem::replace(self, Replay::Off) {
+            Replay::Off => Ok("Not recording or playing back".to_owned()),
+            Replay::Recording(recorder) => {
+                let count = recorder.finish()?;
+                Ok(format!("Recording stopped ({} events)", count))
+            },
+            Replay::Playing(_) => {
+                // Stop whatever the recording was moving. Keys held now were
+                // ignored while it played, and their release does nothing.
+                state.set_held_movement([0; 8]);
+                Ok("Playback stopped".to_owned())
+            },
+        }
+    }
+}
+
+const START_STOP: &'static [&'static str] = &["start", "stop"];
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(Command::new(
+        "record", vec![ArgSpec::required("action", ArgKind::Choice(START_STOP)),
+                       ArgSpec::optional("file", ArgKind::Rest)],
+        "Start recording movement input to a file, or stop", record));
+    registry.register(Command::new(
+        "playback", vec![ArgSpec::required("action", ArgKind::Choice(START_STOP)),
+                         ArgSpec::optional("file", ArgKind::Rest)],
+        "Start playing back recorded movement input from a file, or stop", playback));
+}
+
+fn record(state: &State, args: &Args) -> Result<Option<String>> {
+    let mut replay = state.replay.lock().unwrap();
+    if args.word(0) == "stop" {
+        return Ok(Some(replay.stop(state)?));
+    }
+    if args.len() < 2 {
+        return Err(ErrorKind::Command("Usage: record start <file>".to_owned()).into());
+    }
+    replay.stop(state)?;
+    let path = Path::new(args.word(1));
+    *replay = Replay::Recording(Recorder::new(path)?);
+    Ok(Some(format!("Recording to {}", path.display())))
+}
+
+fn playback(state: &State, args: &Args) -> Result<Option<String>> {
+    let mut replay = state.replay.lock().unwrap();
+    if args.word(0) == "stop" {
+        return Ok(Some(replay.stop(state)?));
+    }
+    if args.len() < 2 {
+        return Err(ErrorKind::Command("Usage: playback start <file>".to_owned()).into());
+    }
+    replay.stop(state)?;
+    let path = Path::new(args.word(1));
+    *replay = Replay::Playing(Playback::new(path)?);
+    Ok(Some(format!("Playing back {}", path.display())))
+}
@@ -0,0 +1,271 @@
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use siege_math::{Point3, Vec3};
+use siege_plugin_avatar_simple::{Placement, MoveDirection};
+use bindings::Action;
+use camera::copy_placement;
+use config::Config;
+use state::State;
+use errors::*;
+
+/// Things input asks the simulation to do. They take effect at the next tick.
+pub enum SimCommand {
+    /// Start or stop one of the movement actions
+    Move { action: Action, positive: bool },
+    /// Turn by (yaw, pitch) radians
+    Look { yaw: f32, pitch: f32 },
+}
+
+// How fast the avatar walks (m/s) and turns (radians/s)
+const WALK_SPEED: f32 = 4.0;
+const TURN_RATE: f32 = 90.0 * PI / 180.0;
+
+// Indices into Motion::held
+const FORWARD: usize = 0;
+const BACKWARD: usize = 1;
+const LEFT: usize = 2;
+const RIGHT: usize = 3;
+const TURN_LEFT: usize = 4;
+const TURN_RIGHT: usize = 5;
+const PITCH_UP: usize = 6;
+const PITCH_DOWN: usize = 7;
+
+/// Which ways the avatar is moving. Movement commands only say when moving
+/// starts and stops, so the avatar is moved on every tick, by the tick length,
+/// so that the same ticks always take it to the same place.
+pub struct Motion {
+    // How many keys are holding each direction (a direction can be bound to
+    // several keys)
+    held: [u32; 8],
+    // Seconds per tick
+    dt: f32,
+}
+
+impl Motion {
+    pub fn new(tick_rate: u32) -> Motion {
+        Motion {
+            held: [0; 8],
+            dt: 1.0 / tick_rate.max(1) as f32,
+        }
+    }
+
+    /// Start (positive) or stop moving in a direction
+    pub fn set(&mut self, direction: MoveDirection, positive: bool) {
+        #[allow(unreachable_patterns)]
+        let index = match direction {
+            MoveDirection::Forward => FORWARD,
+            MoveDirection::Backward => BACKWARD,
+            MoveDirection::StrafeLeft => LEFT,
+            MoveDirection::StrafeRight => RIGHT,
+            MoveDirection::YawLeft => TURN_LEFT,
+            MoveDirection::YawRight => TURN_RIGHT,
+            MoveDirection::PitchUp => PITCH_UP,
+            MoveDirection::PitchDown => PITCH_DOWN,
+            _ => return,
+        };
+        if positive {
+            self.held[index] += 1;
+        } else {
+            self.held[index] = self.held[index].saturating_sub(1);
+        }
+    }
+
+    /// How many keys are holding each direction, e.g. to save and restore
+    pub fn held(&self) -> [u32; 8] {
+        self.held
+    }
+
+    pub fn set_held(&mut self, held: [u32; 8]) {
+        self.held = held;
+    }
+
+    // -1, 0 or 1, depending on which of two opposite directions are held
+    fn axis(&self, positive: usize, negative: usize) -> f32 {
+        ((self.held[positive] > 0) as i32 - (self.held[negative] > 0) as i32) as f32
+    }
+
+    /// Move a placement on by one tick. Returns the (yaw, pitch) to turn by.
+    pub fn step(&self, placement: &mut Placement) -> (f32, f32) {
+        let ahead = self.axis(FORWARD, BACKWARD) * WALK_SPEED * self.dt;
+        let across = self.axis(RIGHT, LEFT) * WALK_SPEED * self.dt;
+        let yaw = placement.yaw;
+        // Walking stays level, whatever the pitch
+        let p = &mut placement.position.0;
+        p.x += -yaw.sin() * ahead + yaw.cos() * across;
+        p.z += yaw.cos() * ahead + yaw.sin() * across;
+        (self.axis(TURN_LEFT, TURN_RIGHT) * TURN_RATE * self.dt,
+         self.axis(PITCH_UP, PITCH_DOWN) * TURN_RATE * self.dt)
+    }
+}
+
+/// A fixed-rate clock. Real time is accumulated, and spent in whole ticks.
+pub struct SimClock {
+    tick: Duration,
+    max_catch_up: u32,
+    accumulator: Duration,
+    last: Instant,
+    /// The simulated time of the next tick
+    sim_time: Instant,
+}
+
+impl SimClock {
+    pub fn new(tick_rate: u32, max_catch_up: u32) -> SimClock {
+        let now = Instant::now();
+        SimClock {
+            tick: Duration::new(0, 1_000_000_000 / tick_rate.max(1)),
+            max_catch_up: max_catch_up.max(1),
+            accumulator: Duration::new(0, 0),
+            last: now,
+            sim_time: now,
+        }
+    }
+
+    /// Account for the time that has passed, returning the simulated times of
+    /// the ticks that are now due (oldest first)
+    pub fn advance(&mut self, now: Instant) -> Vec<Instant> {
+        self.accumulator += now.duration_since(self.last);
+        self.last = now;
+
+        let mut ticks = Vec::new();
+        while self.accumulator >= self.tick {
+            if ticks.len() as u32 == self.max_catch_up {
+                // Too far behind; drop the time rather than spiral
+                warn!("Simulation is running behind, skipping {:?}", self.accumulator);
+                self.sim_time += self.accumulator;
+                self.accumulator = Duration::new(0, 0);
+                break;
+            }
+            self.accumulator -= self.tick;
+            ticks.push(self.sim_time);
+            self.sim_time += self.tick;
+        }
+        ticks
+    }
+
+    /// How long until the next tick is due
+    pub fn until_next(&self) -> Duration {
+        self.tick - self.accumulator
+    }
+}
+
+fn seconds(d: Duration) -> f32 {
+    d.as_secs() as f32 + d.subsec_nanos() as f32 * 1e-9
+}
+
+fn fraction(part: Duration, whole: Duration) -> f32 {
+    (seconds(part) / seconds(whole)).max(0.0).min(1.0)
+}
+
+/// The avatar placement at the last two ticks, so that rendering can
+/// interpolate between them
+pub struct SimFrame {
+    previous: Placement,
+    current: Placement,
+    // The simulated time the current placement is for, and how long ticks are
+    ran_at: Instant,
+    tick: Duration,
+}
+
+impl SimFrame {
+    pub fn new(placement: Placement, tick_rate: u32) -> SimFrame {
+        SimFrame {
+            previous: copy_placement(&placement),
+            current: placement,
+            ran_at: Instant::now(),
+            tick: Duration::new(0, 1_000_000_000 / tick_rate.max(1)),
+        }
+    }
+
+    /// Move on to a new tick. The tick at `when` runs until the next one, so
+    /// its placement is for a tick later.
+    pub fn push(&mut self, placement: Placement, when: Instant) {
+        self.previous = ::std::mem::replace(&mut self.current, placement);
+        self.ran_at = when + self.tick;
+    }
+
+    /// The interpolation alpha: how far real time is past the simulated time
+    /// of the current placement, as a fraction of a tick
+    pub fn alpha(&self) -> f32 {
+        fraction(Instant::now().duration_since(self.ran_at), self.tick)
+    }
+
+    /// The placement to render now, between the last two ticks
+    pub fn interpolated(&self) -> Placement {
+        let alpha = self.alpha();
+        let lerp = |a: f32, b: f32| a + (b - a) * alpha;
+        let (p, c) = (&self.previous.position.0, &self.current.position.0);
+
+        // Yaw wraps around, so go the short way
+        let mut yaw_delta = (self.current.yaw - self.previous.yaw) % (2.0 * PI);
+        if yaw_delta > PI {
+            yaw_delta -= 2.0 * PI;
+        } else if yaw_delta < -PI {
+            yaw_delta += 2.0 * PI;
+        }
+
+        Placement::new(
+            Point3(Vec3::new(lerp(p.x, c.x), lerp(p.y, c.y), lerp(p.z, c.z))),
+            lerp(self.previous.pitch, self.current.pitch),
+            self.previous.yaw + yaw_delta * alpha)
+    }
+}
+
+/// Runs the simulation at a fixed rate, in its own thread
+pub struct SimSystem {
+    clock: SimClock,
+    state: Arc<State>,
+}
+
+impl SimSystem {
+    pub fn new(state: Arc<State>, config: Arc<Config>) -> SimSystem
+    {
+        SimSystem {
+            clock: SimClock::new(config.simulation.tick_rate, config.simulation.max_catch_up),
+            state: state,
+        }
+    }
+
+    pub fn run(&mut self) -> Result<()>
+    {
+        while !self.state.terminating.load(Ordering::Relaxed) {
+            for when in self.clock.advance(Instant::now()) {
+                self.state.sim_tick(when);
+            }
+            ::std::thread::sleep(self.clock.until_next());
+        }
+        trace!("Simulation system loop has completed.");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use siege_plugin_avatar_simple::MoveDirection;
+    use super::{Motion, FORWARD, BACKWARD};
+
+    #[test]
+    fn test_motion_two_keys() {
+        // e.g. W and Up both held, then let go in the order they were pressed
+        let mut motion = Motion::new(60);
+        motion.set(MoveDirection::Forward, true);
+        motion.set(MoveDirection::Forward, true);
+        assert_eq!(motion.axis(FORWARD, BACKWARD), 1.0);
+        motion.set(MoveDirection::Forward, false);
+        assert_eq!(motion.axis(FORWARD, BACKWARD), 1.0);
+        motion.set(MoveDirection::Forward, false);
+        assert_eq!(motion.axis(FORWARD, BACKWARD), 0.0);
+
+        // A stray release does not start moving the other way
+        motion.set(MoveDirection::Forward, false);
+        assert_eq!(motion.axis(FORWARD, BACKWARD), 0.0);
+
+        motion.set(MoveDirection::Backward, true);
+        motion.set(MoveDirection::Forward, true);
+        assert_eq!(motion.axis(FORWARD, BACKWARD), 0.0);
+        motion.set(MoveDirection::Forward, false);
+        assert_eq!(motion.axis(FORWARD, BACKWARD), -1.0);
+    }
+}
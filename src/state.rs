@@ -1,23 +1,73 @@
 
-use std::sync::atomic::AtomicBool;
-use std::sync::{Arc, RwLock};
+use std::f32::consts::PI;
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Instant;
 use ring::rand::SystemRandom;
-use siege_math::{Point3, Vec3, Angle};
+use siege_math::{Point3, Vec3, Vec4};
 use network::PacketSender;
 use errors::*;
 use siege_plugin_avatar_simple::{Avatar, Placement, Movement, MoveDirection};
-use camera::Camera;
+use camera::{self, Camera, Bounds, copy_placement};
 use terrain::Terrain;
 use config::Config;
-use ui::Ui;
+use ui::{Ui, TextColor};
 use stats::Stats;
-use chat::Chat;
+use chat::{self, Chat, Channel};
+use command::{self, CommandRegistry};
+use cvar::{self, CvarRegistry};
+use logger::ConsoleQueue;
+use bindings::{self, Bindings, Action};
+use replay::{self, Replay, InputEvent};
+use camera_path::{self, CameraPath};
+use labels::{self, WorldLabels};
+use sim::{SimCommand, SimFrame, Motion};
+use crossbeam::sync::MsQueue;
+use log::Level;
 
+// Mouse-look stops short of looking straight up or down
+const MAX_PITCH: f32 = 85.0 * PI / 180.0;
+
+// How many scripts can be running at once (scripts can run other scripts)
+const MAX_SCRIPT_DEPTH: usize = 8;
+
+/// Rendering parameters that can be changed while running (see cvar.rs).
+/// The graphics system picks these up every frame.
 pub struct RenderParams {
     pub bloom_strength: f32,
     pub bloom_cliff: f32,
     pub blur_level: f32,
+    pub ambient: f32,
+    pub white_level: f32,
+    /// Directions of the two directional lights, in world space
+    pub light_dir: Vec4<f32>,
+    pub light_dir_2: Vec4<f32>,
+}
+
+impl Default for RenderParams {
+    fn default() -> RenderParams {
+        RenderParams {
+            bloom_strength: 0.60,
+            bloom_cliff: 0.35,
+            blur_level: 0.0,
+            ambient: 0.08,
+            white_level: 1.0,
+            light_dir: Vec4 {
+                x:  0.5773502691896258,
+                y: -0.5773502691896258,
+                z:  0.5773502691896258,
+                w: 0.0,
+            },
+            light_dir_2: Vec4 {
+                x: -0.8017837257372732,
+                y: -0.2672612419124244,
+                z: -0.5345224838248488,
+                w: 0.0,
+            },
+        }
+    }
 }
 
 // State shared between threads
@@ -32,19 +82,54 @@ pub struct State {
     pub avatar: RwLock<Avatar>,
     pub camera: RwLock<Camera>,
     pub terrain: Terrain, // read only
+    /// Where the grayboxes are, kept up to date by the graphics system. Lock
+    /// the camera first if both.
+    pub solids: RwLock<Vec<Bounds>>,
     pub render_params: RwLock<RenderParams>,
     pub ui: Ui,
     pub stats: RwLock<Stats>,
     pub chat: RwLock<Chat>,
+    pub commands: RwLock<CommandRegistry>,
+    pub cvars: CvarRegistry, // read only
+    pub bindings: RwLock<Bindings>,
+    pub replay: Mutex<Replay>,
+    /// Camera keyframes, and their playback. Lock the camera first if both.
+    pub camera_path: Mutex<CameraPath>,
+    /// Text anchored to points in the world. Lock the camera first if both.
+    pub labels: Mutex<WorldLabels>,
+    // Commands for the next simulation tick
+    sim_queue: MsQueue<SimCommand>,
+    // Which ways the avatar is moving (only the simulation changes this)
+    motion: Mutex<Motion>,
+    // The last two ticks, for rendering
+    sim_frame: RwLock<SimFrame>,
+    // Log records waiting to be shown in chat
+    console: ConsoleQueue,
+    // How many scripts are running (see run_script)
+    script_depth: AtomicUsize,
 }
 
 impl State {
-    pub fn new(config: &Config) -> Result<State>
+    pub fn new(config: &Config, console: ConsoleQueue) -> Result<State>
     {
-        let mut ui = Ui::new(&*config.graphics.renderer.asset_path)?;
-        let chat = Chat::new(&mut ui);
+        let ui = Ui::new(&*config.graphics.renderer.asset_path, &*config.window.layout_file,
+                         config.window.width, config.window.height)?;
+        let chat = Chat::new(&ui, &config.chat);
+
+        let mut commands = CommandRegistry::new();
+        command::register_builtins(&mut commands);
+        chat::register_commands(&mut commands);
+        cvar::register_commands(&mut commands);
+        bindings::register_commands(&mut commands);
+        replay::register_commands(&mut commands);
+        camera::register_commands(&mut commands);
+        camera_path::register_commands(&mut commands);
+        labels::register_commands(&mut commands);
 
-        Ok(State {
+        let mut cvars = CvarRegistry::new();
+        cvar::register_builtins(&mut cvars);
+
+        let state = State {
             start: Instant::now(),
             terminating: Arc::new(AtomicBool::new(false)),
             rng: Arc::new(SystemRandom::new()),
@@ -57,84 +142,254 @@ impl State {
                     0.0),  // no yaw, looking down +Z
                 Movement::new(),
                 Instant::now())),
+            sim_queue: MsQueue::new(),
+            motion: Mutex::new(Motion::new(config.simulation.tick_rate)),
+            sim_frame: RwLock::new(SimFrame::new(
+                Placement::new(Point3(Vec3::new(0.0, 0.0, 0.0)), 0.0, 0.0),
+                config.simulation.tick_rate)),
             camera: RwLock::new(Camera::new(config)),
+            solids: RwLock::new(Vec::new()),
             terrain: Terrain::new(config, "sample_terrain")?,
-            render_params: RwLock::new(RenderParams {
-                bloom_strength: 0.60,
-                bloom_cliff: 0.35,
-                blur_level: 0.0,
-            }),
+            render_params: RwLock::new(Default::default()),
             ui: ui,
             stats: RwLock::new(Stats::new()),
             chat: RwLock::new(chat),
-        })
+            commands: RwLock::new(commands),
+            cvars: cvars,
+            bindings: RwLock::new(Bindings::new(&config.bindings)),
+            replay: Mutex::new(Replay::Off),
+            camera_path: Mutex::new(CameraPath::new()),
+            labels: Mutex::new(WorldLabels::new()),
+            console: console,
+            script_depth: AtomicUsize::new(0),
+        };
+
+        // Apply cvars from the config file
+        for (name, value) in &config.cvars {
+            let text = match *value {
+                ::toml::Value::String(ref s) => s.clone(),
+                ref v => v.to_string(),
+            };
+            if let Err(e) = state.cvars.get(name).and_then(|c| c.set(&state, &*text)) {
+                warn!("Config cvar {}: {}", name, e);
+            }
+        }
+
+        Ok(state)
     }
 
-    pub fn periodic_update(&self) {
-        // Update camera (movement commands are only change events, but camera needs
-        // update continuously while movement is happening)
+    /// Run one simulation tick. `when` is the simulated time of the tick;
+    /// commands queued since the last tick take effect then.
+    pub fn sim_tick(&self, when: Instant) {
         {
-            let mut placement = {
-                self.avatar.read().unwrap().get_current_placement()
-            };
+            // Play back recorded input, or record the commands of this tick
+            let mut replay = self.replay.lock().unwrap();
+            replay.update(self);
+
+            while let Some(command) = self.sim_queue.try_pop() {
+                match command {
+                    SimCommand::Move { action, positive } => {
+                        if let Some(direction) = action.move_direction() {
+                            self.apply_movement(direction, positive);
+                        }
+                        replay.record(if positive {
+                            InputEvent::Start(action)
+                        } else {
+                            InputEvent::End(action)
+                        });
+                    },
+                    SimCommand::Look { yaw, pitch } => {
+                        self.apply_look(yaw, pitch);
+                        replay.record(InputEvent::Look(yaw, pitch));
+                    },
+                }
+            }
+        }
+
+        // Movement commands are only change events, so the avatar moves on
+        // every tick while movement is happening, by exactly one tick
+        let (yaw, pitch) = {
+            let mut avatar = self.avatar.write().unwrap();
+            self.motion.lock().unwrap().step(&mut avatar.placement)
+        };
+        self.apply_look(yaw, pitch);
+
+        let placement = {
+            let mut avatar = self.avatar.write().unwrap();
 
             // Place above terrain
-            placement.position.0.y = self.terrain.get_y(
-                placement.position.0.x,
-                placement.position.0.z
+            avatar.placement.position.0.y = self.terrain.get_y(
+                avatar.placement.position.0.x,
+                avatar.placement.position.0.z
             );
+            copy_placement(&avatar.placement)
+        };
+
+        self.sim_frame.write().unwrap().push(placement, when);
+    }
+
+    /// Move the camera to follow the avatar, interpolating between the last two
+    /// simulation ticks and smoothing, or fly the free camera, or follow a
+    /// camera path. Returns the camera placement.
+    pub fn update_camera(&self) -> Placement {
+        let now = Instant::now();
+        let avatar = self.sim_frame.read().unwrap().interpolated();
+        let mut camera = self.camera.write().unwrap();
+        let follow = camera.follow_placement(&avatar, &self.terrain);
+        let follow = camera.smooth(follow, &self.terrain, now);
+        let on_path = self.camera_path.lock().unwrap().update(&mut camera, now);
+        let speed = camera.free_speed;
+        let view = match (on_path, camera.free.as_mut()) {
+            (Some(placement), free) => {
+                // The free camera waits where it was until the path is done
+                if let Some(free) = free {
+                    free.hold(now);
+                }
+                placement
+            },
+            (None, Some(free)) => {
+                free.update(now, speed);
+                free.placement()
+            },
+            (None, None) => copy_placement(&follow),
+        };
+        // A frozen frustum stays with the avatar's view, so the culling can be
+        // seen from outside
+        let cull = if camera.freeze_culling { follow } else { copy_placement(&view) };
+        camera.update_placement(&view, &cull);
+        self.labels.lock().unwrap().update(&self.ui, &camera);
+        view
+    }
+
+    /// Detach the camera from the avatar (starting from where it is now), or
+    /// reattach it. Returns true if it is now detached.
+    pub fn toggle_free_camera(&self) -> bool {
+        let avatar = self.sim_frame.read().unwrap().interpolated();
+        let mut camera = self.camera.write().unwrap();
+        let from = camera.follow_placement(&avatar, &self.terrain);
+        camera.toggle_free(&from)
+    }
 
-            let mut camera = self.camera.write().unwrap();
-            camera.update_placement(&placement);
-            /*debug!("Position = ({},{},{})",
-                   placement.position.x,
-                   placement.position.y,
-                   placement.position.z);*/
+    pub fn periodic_update(&self) {
+        let mut chat = self.chat.write().unwrap();
+
+        // Show log records
+        while let Some(record) = self.console.try_pop() {
+            let color = match record.level {
+                Level::Error => TextColor::Red,
+                Level::Warn => TextColor::Gold,
+                Level::Info => TextColor::White,
+                Level::Debug | Level::Trace => TextColor::Gray,
+            };
+            chat.emit_colored(&self.ui, Channel::Console, color, &*record.text);
         }
+
+        // Fade chat lines that have been idle
+        chat.update(&self.ui);
+    }
+
+    /// Start or stop a movement action, at the next simulation tick
+    pub fn movement_cmd(&self, action: Action, positive: bool) {
+        self.sim_queue.push(SimCommand::Move { action: action, positive: positive });
+    }
+
+    /// Start or stop moving now (from the simulation thread, e.g. when played back)
+    pub fn apply_movement(&self, direction: MoveDirection, positive: bool) {
+        self.motion.lock().unwrap().set(direction, positive);
+    }
+
+    /// How many keys are holding each movement direction (see sim::Motion)
+    pub fn held_movement(&self) -> [u32; 8] {
+        self.motion.lock().unwrap().held()
+    }
+
+    /// Replace which movement directions are held, e.g. when playback starts
+    /// or stops
+    pub fn set_held_movement(&self, held: [u32; 8]) {
+        self.motion.lock().unwrap().set_held(held);
+    }
+
+    /// Turn the avatar by the given angles (in radians), at the next simulation tick
+    pub fn look(&self, yaw: f32, pitch: f32) {
+        self.sim_queue.push(SimCommand::Look { yaw: yaw, pitch: pitch });
+    }
+
+    /// Turn the avatar now (from the simulation thread, e.g. when played back)
+    pub fn apply_look(&self, yaw: f32, pitch: f32) {
+        let mut avatar = self.avatar.write().unwrap();
+        avatar.placement.yaw = (avatar.placement.yaw + yaw) % (2.0 * PI);
+        avatar.placement.pitch = (avatar.placement.pitch + pitch).max(-MAX_PITCH).min(MAX_PITCH);
+    }
+
+    /// Say something in chat
+    pub fn say(&self, text: &str) {
+        let text = text.trim();
+        if text.starts_with('/') {
+            return self.run_command(&text[1..]);
+        }
+        if text.is_empty() {
+            return;
+        }
+        // FIXME: send this to the server, once it handles chat
+        let mut chat = self.chat.write().unwrap();
+        chat.emit_message(&self.ui, Channel::Say, "You", text);
+    }
+
+    /// Run a command line (without the leading slash), printing the result to chat
+    pub fn run_command(&self, line: &str) {
+        // Release the registry before running, handlers may need it
+        let prepared = self.commands.read().unwrap().prepare(line);
+        let result = match prepared {
+            Ok(Some((handler, args))) => handler(self, &args),
+            Ok(None) => return,
+            Err(e) => Err(e),
+        };
+
+        let mut chat = self.chat.write().unwrap();
+        match result {
+            Ok(Some(output)) => for line in output.lines() {
+                chat.emit_line(&self.ui, Channel::System, line);
+            },
+            Ok(None) => {},
+            Err(e) => chat.emit_error(&self.ui, &*format!("{}", e)),
+        }
+    }
+
+    /// Run each line of a file as a command. Blank lines and lines starting
+    /// with '#' are skipped, and the leading '/' on commands is optional.
+    pub fn run_script(&self, path: &Path) -> Result<()> {
+        use std::io::{BufRead, BufReader};
+
+        // Scripts can run other scripts, but not forever
+        if self.script_depth.fetch_add(1, Ordering::SeqCst) >= MAX_SCRIPT_DEPTH {
+            self.script_depth.fetch_sub(1, Ordering::SeqCst);
+            return Err(ErrorKind::Command(
+                format!("Scripts nested too deeply, not running {}", path.display())).into());
+        }
+
+        let run = || -> Result<()> {
+            let file = File::open(path)?;
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                self.run_command(line.trim_start_matches('/'));
+            }
+            Ok(())
+        };
+        let result = run();
+
+        self.script_depth.fetch_sub(1, Ordering::SeqCst);
+        result
     }
 
-    pub fn movement_cmd(&self, direction: MoveDirection, positive: bool) {
+    /// Move the avatar to a position on the terrain
+    pub fn teleport(&self, x: f32, z: f32) {
         let mut avatar = self.avatar.write().unwrap();
-        avatar.movement_cmd(
-            direction, positive, Instant::now());
-
-        // Place above terrain
-        avatar.placement.position.0.y = self.terrain.get_y(
-            avatar.placement.position.0.x,
-            avatar.placement.position.0.z
-        );
-    }
-
-    pub fn adjust_fovx(&self, angle: Angle<f32>) {
-        self.camera.write().unwrap().adjust_fovx(angle);
-    }
-
-    pub fn adjust_bloom_strength(&self, positive: bool) {
-        let mut bloom_strength = self.render_params.read().unwrap().bloom_strength;
-        let delta = if positive { 0.01 } else { -0.01 };
-        bloom_strength += delta;
-        if bloom_strength < 0.0 { bloom_strength = 0.0; }
-        if bloom_strength > 1.0 { bloom_strength = 1.0; }
-        info!("Bloom strength = {}", bloom_strength);
-        self.render_params.write().unwrap().bloom_strength = bloom_strength;
-    }
-
-    pub fn adjust_bloom_cliff(&self, positive: bool) {
-        let mut bloom_cliff = self.render_params.read().unwrap().bloom_cliff;
-        let delta = if positive { 0.01 } else { -0.01 };
-        bloom_cliff += delta;
-        if bloom_cliff < 0.0 { bloom_cliff = 0.0; }
-        if bloom_cliff > 1.0 { bloom_cliff = 1.0; }
-        info!("Bloom cliff = {}", bloom_cliff);
-        self.render_params.write().unwrap().bloom_cliff = bloom_cliff;
-    }
-
-    pub fn adjust_blur_level(&self, positive: bool) {
-        let mut blur_level = self.render_params.read().unwrap().blur_level;
-        let delta = if positive { 0.01 } else { -0.01 };
-        blur_level += delta;
-        if blur_level < 0.0 { blur_level = 0.0; }
-        if blur_level > 1.0 { blur_level = 1.0; }
-        self.render_params.write().unwrap().blur_level = blur_level;
+        avatar.placement.position.0.x = x;
+        avatar.placement.position.0.z = z;
+        avatar.placement.position.0.y = self.terrain.get_y(x, z);
     }
 }
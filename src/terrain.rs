@@ -4,10 +4,20 @@ use std::io::Read;
 use siege_math::Vec2;
 use errors::*;
 use config::Config;
+use camera::Ray;
 
 pub const WIDTH: usize = 513;
 pub const HEIGHT: usize = 513;
 
+/// How far above the ground get_y is, in meters
+pub const EYE_LEVEL: f32 = 2.0;
+
+// How far apart we check the terrain along a ray, in meters
+const RAY_STEP: f32 = 0.5;
+
+// The furthest a ray is followed (it is followed in steps)
+const MAX_RAY_DISTANCE: f32 = 10000.0;
+
 pub struct Terrain {
     heightmap: Vec<u8>,
 }
@@ -84,9 +94,50 @@ impl Terrain {
                          + lower_right_height * lower_right_weight) / sum;
 
         // Add a couple of meters (eye level)
-        value += 2.0 * QUANTA_PER_METER;
+        value += EYE_LEVEL * QUANTA_PER_METER;
 
         // Translate from quanta into y coordinate value
         32.0 - (value / QUANTA_PER_METER)
     }
+
+    /// The height of the ground itself (get_y is at eye level above it)
+    pub fn ground_y(&self, x: f32, z: f32) -> f32 {
+        self.get_y(x, z) + EYE_LEVEL
+    }
+
+    /// How far along a ray it first meets the ground, if within max_distance
+    /// (which is kept between 0 and MAX_RAY_DISTANCE)
+    pub fn ray_hit(&self, ray: &Ray, max_distance: f32) -> Option<f32> {
+        // (a NaN becomes 0, and infinity would never be reached)
+        let max_distance = max_distance.max(0.0).min(MAX_RAY_DISTANCE);
+
+        // (smaller y is higher)
+        let below = |t: f32| {
+            let p = ray.at(t).0;
+            p.y >= self.ground_y(p.x, p.z)
+        };
+        if below(0.0) {
+            return Some(0.0);
+        }
+
+        // Step along until under the ground, then narrow down where it crossed
+        let mut before = 0.0;
+        while before < max_distance {
+            let after = (before + RAY_STEP).min(max_distance);
+            if below(after) {
+                let (mut above, mut under) = (before, after);
+                for _ in 0..12 {
+                    let middle = (above + under) / 2.0;
+                    if below(middle) {
+                        under = middle;
+                    } else {
+                        above = middle;
+                    }
+                }
+                return Some(under);
+            }
+            before = after;
+        }
+        None
+    }
 }
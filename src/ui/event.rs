@@ -0,0 +1,48 @@
+
+use std::sync::Arc;
+use winit::{MouseButton, ModifiersState, VirtualKeyCode};
+use super::{Ui, Handle};
+
+/// Events delivered to UI nodes. Positions are in screen pixels. Keyboard
+/// events go to the focused node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UiEvent {
+    MouseDown { button: MouseButton, x: f32, y: f32 },
+    MouseUp { button: MouseButton, x: f32, y: f32 },
+    MouseMove { x: f32, y: f32 },
+    /// The cursor moved onto this node (it is now the frontmost node under it)
+    HoverEnter,
+    /// The cursor moved off of this node
+    HoverLeave,
+    /// Scrolling, in lines (positive y is away from the user)
+    Wheel { dx: f32, dy: f32 },
+    /// A key was pressed (or repeated). Nodes that take text should handle the
+    /// keys they type with, so that those keys do not also reach the bindings.
+    KeyDown { key: VirtualKeyCode, modifiers: ModifiersState },
+    /// A key that was handled when it went down came back up
+    KeyUp { key: VirtualKeyCode },
+    /// A character was typed
+    Character(char),
+    /// This node now has the keyboard focus
+    FocusGained,
+    /// This node no longer has the keyboard focus
+    FocusLost,
+    /// The screen changed size (only root nodes get this)
+    ScreenResized { width: f32, height: f32 },
+}
+
+impl UiEvent {
+    /// Hover, focus and resize events are only for the node itself; the rest bubble up
+    /// to the parents until some listener handles them.
+    pub fn bubbles(&self) -> bool {
+        match *self {
+            UiEvent::HoverEnter | UiEvent::HoverLeave |
+            UiEvent::FocusGained | UiEvent::FocusLost |
+            UiEvent::ScreenResized { .. } => false,
+            _ => true,
+        }
+    }
+}
+
+/// Listeners return true if they handled the event
+pub type Listener = Arc<Fn(&Ui, Handle, &UiEvent) -> bool + Send + Sync>;
@@ -7,3 +7,27 @@ pub struct AbsRect {
     pub width: f32,
     pub height: f32,
 }
+
+impl AbsRect {
+    /// Whether a screen point falls within this rect (the right and bottom
+    /// edges are outside, as they are for pixels)
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && x < self.x + self.width &&
+            y >= self.y && y < self.y + self.height
+    }
+
+    /// The part of this rect that is also within the other one. If they do not
+    /// overlap, the result has zero width or height.
+    pub fn intersect(&self, other: &AbsRect) -> AbsRect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        AbsRect {
+            x: x,
+            y: y,
+            width: (right - x).max(0.0),
+            height: (bottom - y).max(0.0),
+        }
+    }
+}
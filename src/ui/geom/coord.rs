@@ -36,6 +36,14 @@ impl Coord {
         }
     }
 
+    #[inline]
+    pub fn plus_pixels(&self, pixels: i32) -> Coord {
+        Coord {
+            anchor: self.anchor,
+            dim: self.dim.plus_pixels(pixels),
+        }
+    }
+
     pub fn absolute_xpoint(&self, abs_parent: &AbsRect) -> f32 {
         let anch = if self.anchor == Anchor::BottomOrRight {
             abs_parent.width
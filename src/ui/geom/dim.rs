@@ -17,6 +17,14 @@ impl Dim {
         }
     }
 
+    #[inline]
+    pub fn plus_pixels(&self, pixels: i32) -> Dim {
+        Dim {
+            fraction: self.fraction,
+            pixel_offset: self.pixel_offset + pixels,
+        }
+    }
+
     #[inline]
     pub fn absolute_xdim(&self, abs_parent: &AbsRect) -> f32 {
         abs_parent.width * self.fraction + self.pixel_offset as f32
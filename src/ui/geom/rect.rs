@@ -35,6 +35,15 @@ impl Rect {
         }
     }
 
+    /// The same rect with its edges moved by some pixels (positive is right or
+    /// down), keeping the same anchors
+    pub fn with_edges_moved(&self, left: i32, top: i32, right: i32, bottom: i32) -> Rect {
+        Rect {
+            rectx: self.rectx.with_edges_moved(left, right),
+            recty: self.recty.with_edges_moved(top, bottom),
+        }
+    }
+
     pub fn absolute(&self, abs_parent: &AbsRect) -> AbsRect {
         let mut absrect = AbsRect {
             x: match self.rectx {
@@ -79,6 +88,34 @@ impl Rect {
     }
 }
 
+impl RectX {
+    /// Move the left and right edges by some pixels
+    pub fn with_edges_moved(&self, left: i32, right: i32) -> RectX {
+        match *self {
+            RectX::LeftRight(l, r) =>
+                RectX::LeftRight(l.plus_pixels(left), r.plus_pixels(right)),
+            RectX::LeftWidth(l, w) =>
+                RectX::LeftWidth(l.plus_pixels(left), w.plus_pixels(right - left)),
+            RectX::RightWidth(r, w) =>
+                RectX::RightWidth(r.plus_pixels(right), w.plus_pixels(right - left)),
+        }
+    }
+}
+
+impl RectY {
+    /// Move the top and bottom edges by some pixels
+    pub fn with_edges_moved(&self, top: i32, bottom: i32) -> RectY {
+        match *self {
+            RectY::TopBottom(t, b) =>
+                RectY::TopBottom(t.plus_pixels(top), b.plus_pixels(bottom)),
+            RectY::TopHeight(t, h) =>
+                RectY::TopHeight(t.plus_pixels(top), h.plus_pixels(bottom - top)),
+            RectY::BottomHeight(b, h) =>
+                RectY::BottomHeight(b.plus_pixels(bottom), h.plus_pixels(bottom - top)),
+        }
+    }
+}
+
 impl Default for Rect {
     fn default() -> Rect {
         Rect {
@@ -0,0 +1,54 @@
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use toml;
+use errors::*;
+
+/// How far the user has moved each edge of a window from where the program
+/// put it, in pixels. Positive is right or down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct EdgeOffsets {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+/// Where the user has moved windows to, by window name
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UiLayout {
+    #[serde(default)]
+    windows: BTreeMap<String, EdgeOffsets>,
+}
+
+impl UiLayout {
+    /// Load the layout from a file. A missing file gives an empty layout.
+    pub fn load(path: &Path) -> Result<UiLayout>
+    {
+        if !path.is_file() {
+            return Ok(Default::default());
+        }
+        let mut contents: String = String::new();
+        let mut file = File::open(path)?;
+        file.read_to_string(&mut contents)?;
+        Ok(toml::from_str(&*contents)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()>
+    {
+        let contents = toml::to_string(self)?;
+        let mut file = File::create(path)?;
+        file.write_all(contents.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> EdgeOffsets {
+        self.windows.get(name).cloned().unwrap_or_default()
+    }
+
+    pub fn set(&mut self, name: &str, offsets: EdgeOffsets) {
+        self.windows.insert(name.to_owned(), offsets);
+    }
+}
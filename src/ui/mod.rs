@@ -1,8 +1,10 @@
 
+use std::collections::{HashMap, HashSet};
 use std::sync::RwLock;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use chashmap::{CHashMap, ReadGuard};
 use siege_font::FontAtlas;
 use errors::*;
@@ -23,7 +25,15 @@ pub use self::image::{UiImage,
                       WINDOW_L_RULE, WINDOW_RULE, WINDOW_R_RULE};
 
 mod text;
-pub use self::text::{TextColor, Font, TextLine};
+pub use self::text::{TextColor, Font, TextLine, TextStyle, TextSpan, StyledLine,
+                     parse_markup, plain_text};
+
+mod event;
+pub use self::event::{UiEvent, Listener};
+
+mod layout;
+use self::layout::UiLayout;
+pub use self::layout::EdgeOffsets;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Handle(pub usize);
@@ -33,6 +43,7 @@ pub enum UiElement {
     Window(UiWindow),
     Image(UiImage),
     Text(TextLine),
+    Styled(StyledLine),
 }
 
 impl UiElement {
@@ -41,6 +52,7 @@ impl UiElement {
             UiElement::Window(ref win) => Some(win.rect.clone()),
             UiElement::Image(_) => None, // maybe FIXME?
             UiElement::Text(_) => None,
+            UiElement::Styled(_) => None,
         }
     }
 
@@ -49,6 +61,7 @@ impl UiElement {
             UiElement::Window(ref win) => win.child_alpha,
             UiElement::Image(_) => 1.0,
             UiElement::Text(ref tl) => tl.alpha as f32 / 255.0,
+            UiElement::Styled(ref sl) => sl.alpha as f32 / 255.0,
         }
     }
 }
@@ -58,9 +71,16 @@ impl UiElement {
 /// the element.
 pub struct UiNode {
     pub element: UiElement,
+    pub parent: Option<Handle>,
     pub children: Vec<Handle>,
 }
 
+// Which node has the keyboard focus, and which nodes can take it
+struct Focus {
+    focused: Option<Handle>,
+    focusable: HashSet<Handle>,
+}
+
 pub struct Ui {
     atlas: FontAtlas,
     text_is_dirty: AtomicBool,
@@ -68,11 +88,18 @@ pub struct Ui {
     image_is_dirty: AtomicBool,
     map: CHashMap<Handle, UiNode>,
     roots: RwLock<Vec<Handle>>,
+    listeners: RwLock<HashMap<Handle, Vec<Listener>>>,
+    focus: RwLock<Focus>,
+    screen: RwLock<AbsRect>,
+    // Where the user has put movable windows, and where to save that
+    layout: Mutex<UiLayout>,
+    layout_file: PathBuf,
     next_unused_id: AtomicUsize,
 }
 
 impl Ui {
-    pub fn new(asset_path: &Path) -> Result<Ui>
+    pub fn new(asset_path: &Path, layout_file: &Path, width: u32, height: u32)
+               -> Result<Ui>
     {
         let atlas: FontAtlas = {
             let file = File::open(asset_path.join("fonts")
@@ -87,6 +114,25 @@ impl Ui {
             image_is_dirty: AtomicBool::new(true),
             map: CHashMap::new(),
             roots: RwLock::new(vec![]),
+            listeners: RwLock::new(HashMap::new()),
+            focus: RwLock::new(Focus {
+                focused: None,
+                focusable: HashSet::new(),
+            }),
+            screen: RwLock::new(AbsRect {
+                x: 0.0,
+                y: 0.0,
+                width: width as f32,
+                height: height as f32,
+            }),
+            layout: Mutex::new(match UiLayout::load(layout_file) {
+                Ok(layout) => layout,
+                Err(e) => {
+                    warn!("Unable to load window layout: {}", e);
+                    Default::default()
+                }
+            }),
+            layout_file: layout_file.to_owned(),
             next_unused_id: AtomicUsize::new(1),
         })
     }
@@ -117,6 +163,7 @@ impl Ui {
 
         match element {
             UiElement::Text(_) => self.text_is_dirty.store(true, Ordering::Relaxed),
+            UiElement::Styled(_) => self.text_is_dirty.store(true, Ordering::Relaxed),
             UiElement::Window(_) => self.win_is_dirty.store(true, Ordering::Relaxed),
             UiElement::Image(_) => self.image_is_dirty.store(true, Ordering::Relaxed),
         }
@@ -124,6 +171,7 @@ impl Ui {
         // Insert into the map
         self.map.insert(new_id, UiNode {
             element: element,
+            parent: parent,
             children: vec![]
         });
 
@@ -138,7 +186,7 @@ impl Ui {
     where I: FnOnce() -> UiElement,
           U: FnOnce(&mut UiElement)
     {
-        let iinsert = || { UiNode { element: insert(), children: vec![] } };
+        let iinsert = || { UiNode { element: insert(), parent: None, children: vec![] } };
         let iupdate = |n: &mut UiNode| { update(&mut n.element); };
         self.map.upsert(id, iinsert, iupdate);
         self.text_is_dirty.store(true, Ordering::Relaxed); // because it might be text
@@ -160,6 +208,57 @@ impl Ui {
         false
     }
 
+    pub fn set_spans(&self, id: Handle, spans: Vec<TextSpan>) -> bool
+    {
+        use std::ops::DerefMut;
+        let mut guard = match self.map.get_mut(&id) {
+            Some(guard) => guard,
+            None => return false,
+        };
+        let node: &mut UiNode = guard.deref_mut();
+        if let UiElement::Styled(ref mut styledline) = node.element {
+            styledline.spans = spans;
+            self.text_is_dirty.store(true, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+
+    pub fn set_alpha(&self, id: Handle, alpha: u8) -> bool
+    {
+        use std::ops::DerefMut;
+        let mut guard = match self.map.get_mut(&id) {
+            Some(guard) => guard,
+            None => return false,
+        };
+        let node: &mut UiNode = guard.deref_mut();
+        match node.element {
+            UiElement::Text(ref mut textline) => textline.alpha = alpha,
+            UiElement::Styled(ref mut styledline) => styledline.alpha = alpha,
+            _ => return false,
+        }
+        self.text_is_dirty.store(true, Ordering::Relaxed);
+        true
+    }
+
+    /// Move a text line (within its parent, or on the screen)
+    pub fn set_position(&self, id: Handle, position: Point) -> bool
+    {
+        use std::ops::DerefMut;
+        let mut guard = match self.map.get_mut(&id) {
+            Some(guard) => guard,
+            None => return false,
+        };
+        let node: &mut UiNode = guard.deref_mut();
+        match node.element {
+            UiElement::Text(ref mut textline) => textline.ui_coordinates = position,
+            UiElement::Styled(ref mut styledline) => styledline.ui_coordinates = position,
+            _ => return false,
+        }
+        self.text_is_dirty.store(true, Ordering::Relaxed);
+        true
+    }
+
     pub fn is_text_dirty(&self) -> bool
     {
         self.text_is_dirty.load(Ordering::Relaxed)
@@ -187,13 +286,17 @@ impl Ui {
         self.image_is_dirty.store(false, Ordering::Relaxed);
     }
 
-    #[allow(dead_code)]
     pub fn get_pixel_length(&self, line: &TextLine) -> f32
+    {
+        self.text_length(line.lineheight, &*line.text)
+    }
+
+    fn text_length(&self, lineheight: u8, text: &str) -> f32
     {
         let atlas = &self.atlas;
-        let scale = line.lineheight as f32 / atlas.line_height;
+        let scale = lineheight as f32 / atlas.line_height;
         let mut cursor: f32 = 0.0;
-        for ch in line.text.chars() {
+        for ch in text.chars() {
             let cinfo = match atlas.map.get(&ch) {
                 Some(cinfo) => cinfo,
                 None => continue, // FIXME: use a placeholder character
@@ -203,6 +306,284 @@ impl Ui {
         cursor
     }
 
+    /// Move or resize a window (its children follow)
+    pub fn set_rect(&self, id: Handle, rect: Rect) -> bool
+    {
+        use std::ops::DerefMut;
+        let mut guard = match self.map.get_mut(&id) {
+            Some(guard) => guard,
+            None => return false,
+        };
+        let node: &mut UiNode = guard.deref_mut();
+        if let UiElement::Window(ref mut window) = node.element {
+            window.rect = rect;
+            // Everything inside it moved too
+            self.text_is_dirty.store(true, Ordering::Relaxed);
+            self.win_is_dirty.store(true, Ordering::Relaxed);
+            self.image_is_dirty.store(true, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+
+    pub fn get_rect(&self, id: Handle) -> Option<Rect>
+    {
+        self.map.get(&id).and_then(|node| node.element.get_rect())
+    }
+
+    /// Where a node currently is on the screen
+    pub fn absolute_rect(&self, id: Handle) -> Option<AbsRect>
+    {
+        // Collect the rects from here up to the root
+        let mut rects: Vec<Rect> = vec![];
+        let mut target = Some(id);
+        while let Some(handle) = target {
+            let node = match self.map.get(&handle) {
+                Some(node) => node,
+                None => return None,
+            };
+            if let Some(rect) = node.element.get_rect() {
+                rects.push(rect);
+            }
+            target = node.parent;
+        }
+        Some(rects.iter().rev().fold(self.screen(), |parent, rect| rect.absolute(&parent)))
+    }
+
+    pub fn screen(&self) -> AbsRect
+    {
+        self.screen.read().unwrap().clone()
+    }
+
+    /// Call this whenever the window size changes. The root nodes are told,
+    /// so that they can move to fit.
+    pub fn set_screen_size(&self, width: u32, height: u32)
+    {
+        {
+            let mut screen = self.screen.write().unwrap();
+            screen.width = width as f32;
+            screen.height = height as f32;
+        }
+        let roots: Vec<Handle> = self.roots.read().unwrap().clone();
+        let event = UiEvent::ScreenResized { width: width as f32, height: height as f32 };
+        for root in roots {
+            self.dispatch(root, &event);
+        }
+    }
+
+    /// Where the user last put a movable window (see `UiWindow::make_movable`)
+    pub fn saved_offsets(&self, name: &str) -> EdgeOffsets
+    {
+        self.layout.lock().unwrap().get(name)
+    }
+
+    /// Remember where the user put a movable window, for next time
+    pub fn save_offsets(&self, name: &str, offsets: EdgeOffsets)
+    {
+        let mut layout = self.layout.lock().unwrap();
+        layout.set(name, offsets);
+        if let Err(e) = layout.save(&self.layout_file) {
+            warn!("Unable to save window layout: {}", e);
+        }
+    }
+
+    /// Allow (or disallow) a node to take the keyboard focus
+    pub fn set_focusable(&self, id: Handle, focusable: bool)
+    {
+        let lost = {
+            let mut focus = self.focus.write().unwrap();
+            if focusable {
+                focus.focusable.insert(id);
+                false
+            } else {
+                focus.focusable.remove(&id);
+                focus.focused == Some(id)
+            }
+        };
+        if lost {
+            self.set_focus(None);
+        }
+    }
+
+    pub fn focused(&self) -> Option<Handle>
+    {
+        self.focus.read().unwrap().focused
+    }
+
+    /// Give the keyboard focus to a node (or with None, to nothing). The nodes
+    /// losing and gaining it are told.
+    pub fn set_focus(&self, id: Option<Handle>)
+    {
+        let old = {
+            let mut focus = self.focus.write().unwrap();
+            if focus.focused == id {
+                return;
+            }
+            ::std::mem::replace(&mut focus.focused, id)
+        };
+        if let Some(old) = old {
+            self.dispatch(old, &UiEvent::FocusLost);
+        }
+        if let Some(new) = id {
+            self.dispatch(new, &UiEvent::FocusGained);
+        }
+    }
+
+    /// Focus the nearest node (a clicked one, or one of its ancestors) that
+    /// can take the focus. If there is none, nothing has the focus.
+    pub fn focus_at(&self, id: Option<Handle>)
+    {
+        let mut target = id;
+        while let Some(handle) = target {
+            if self.focus.read().unwrap().focusable.contains(&handle) {
+                break;
+            }
+            target = self.map.get(&handle).and_then(|node| node.parent);
+        }
+        self.set_focus(target);
+    }
+
+    /// Move the focus to the next (or previous) focusable node, in the order
+    /// they are laid out on the screen: top to bottom, then left to right.
+    /// Returns false if there are none.
+    pub fn focus_next(&self, reverse: bool) -> bool
+    {
+        let (order, current) = {
+            let focus = self.focus.read().unwrap();
+            if focus.focusable.is_empty() {
+                return false;
+            }
+            let mut nodes: Vec<NodeInfo> = Walker::walk(self, self.screen()).into_iter()
+                .filter(|nodeinfo| focus.focusable.contains(&nodeinfo.handle))
+                .collect();
+            // (the walk visits children before their parents)
+            nodes.sort_by(|a, b| {
+                (a.rect.y, a.rect.x).partial_cmp(&(b.rect.y, b.rect.x))
+                    .unwrap_or(::std::cmp::Ordering::Equal)
+            });
+            let order: Vec<Handle> = nodes.into_iter().map(|nodeinfo| nodeinfo.handle).collect();
+            (order, focus.focused)
+        };
+        if order.is_empty() {
+            return false;
+        }
+        let index = match current.and_then(|c| order.iter().position(|h| *h == c)) {
+            Some(i) if reverse => (i + order.len() - 1) % order.len(),
+            Some(i) => (i + 1) % order.len(),
+            None if reverse => order.len() - 1,
+            None => 0,
+        };
+        self.set_focus(Some(order[index]));
+        true
+    }
+
+    /// Call a listener for events delivered to a node
+    pub fn add_listener(&self, id: Handle, listener: Listener)
+    {
+        let mut listeners = self.listeners.write().unwrap();
+        listeners.entry(id).or_insert_with(Vec::new).push(listener);
+    }
+
+    /// The node drawn on top at a screen point, if any. Root trees are drawn
+    /// in order, and each node before its children, so this is the deepest
+    /// node drawn last. Parts of a node that are cropped away by its ancestors
+    /// are not hit.
+    pub fn hit_test(&self, x: f32, y: f32) -> Option<Handle>
+    {
+        let screen = self.screen();
+        let roots: Vec<Handle> = self.roots.read().unwrap().clone();
+        // (searching from the front)
+        roots.iter().rev()
+            .filter_map(|root| self.hit_node(*root, &screen, &screen, 1.0, x, y))
+            .next()
+    }
+
+    // The frontmost node at a screen point within a node's tree, given where
+    // its parent is
+    fn hit_node(&self, id: Handle, parent_rect: &AbsRect, parent_clip: &AbsRect,
+                parent_alpha: f32, x: f32, y: f32) -> Option<Handle>
+    {
+        let (rect, clip, alpha, drawn, children) = {
+            let node = match self.map.get(&id) {
+                Some(node) => node,
+                None => return None,
+            };
+            let rect = match node.element.get_rect() {
+                Some(rect) => rect.absolute(parent_rect),
+                None => parent_rect.clone(),
+            };
+            let clip = rect.intersect(parent_clip);
+            let alpha = parent_alpha * node.element.get_alpha();
+            let drawn = self.drawn_rect(&node.element, &rect);
+            (rect, clip, alpha, drawn, node.children.clone())
+        };
+        // Children are within their parent's clip, and are no more visible
+        // than it is
+        if !clip.contains(x, y) || alpha <= 0.0 {
+            return None;
+        }
+        for child in children.iter().rev() {
+            if let Some(hit) = self.hit_node(*child, &rect, &clip, alpha, x, y) {
+                return Some(hit);
+            }
+        }
+        if drawn.contains(x, y) {
+            Some(id)
+        } else {
+            None
+        }
+    }
+
+    // The part of its node's rect that an element draws over
+    fn drawn_rect(&self, element: &UiElement, rect: &AbsRect) -> AbsRect
+    {
+        let (point, lineheight, length) = match *element {
+            UiElement::Window(_) => return rect.clone(),
+            UiElement::Image(ref image) => return image.screen_draw_rect.absolute(rect),
+            UiElement::Text(ref line) =>
+                (line.ui_coordinates, line.lineheight,
+                 self.text_length(line.lineheight, &*line.text)),
+            UiElement::Styled(ref line) =>
+                (line.ui_coordinates, line.lineheight,
+                 self.text_length(line.lineheight, &*plain_text(&*line.spans))),
+        };
+        // Text sits on the line's baseline
+        let (x, y) = point.absolute(rect);
+        AbsRect {
+            x: x,
+            y: y - lineheight as f32,
+            width: length,
+            height: lineheight as f32,
+        }
+    }
+
+    /// Deliver an event to a node. Events that bubble go on to its ancestors
+    /// until a listener handles it. Returns the node whose listener did.
+    pub fn dispatch(&self, id: Handle, event: &UiEvent) -> Option<Handle>
+    {
+        let mut target = Some(id);
+        while let Some(handle) = target {
+            // Listeners may change the ui, so no locks are held while they run
+            let listeners: Vec<Listener> = match self.listeners.read().unwrap().get(&handle) {
+                Some(v) => v.clone(),
+                None => vec![],
+            };
+            for listener in listeners {
+                if listener(self, handle, event) {
+                    return Some(handle);
+                }
+            }
+            if !event.bubbles() {
+                return None;
+            }
+            target = match self.map.get(&handle) {
+                Some(node) => node.parent,
+                None => None,
+            };
+        }
+        None
+    }
+
     pub fn walk<'a>(&'a self, width: f32, height: f32) -> Walker<'a>
     {
         let screen = AbsRect {
@@ -227,6 +608,8 @@ impl Ui {
 pub struct NodeInfo {
     pub handle: Handle,
     pub rect: AbsRect,
+    /// The part of the rect that is not cropped away by its ancestors
+    pub clip: AbsRect,
     pub depth: usize,
     pub alpha: f32
 }
@@ -267,12 +650,14 @@ impl<'a> Walker<'a> {
             };
             let readguard = ui.map.get(&nid).unwrap();
             let root_node = &readguard;
+            let rect = match root_node.element.get_rect() {
+                Some(vp) => vp.absolute(&screen),
+                None => screen.clone()
+            };
             let root_nodeinfo = NodeInfo {
                 handle: nid,
-                rect: match root_node.element.get_rect() {
-                    Some(vp) => vp.absolute(&screen),
-                    None => screen.clone()
-                },
+                clip: rect.intersect(&screen),
+                rect: rect,
                 depth: maxdepth,
                 alpha: root_node.element.get_alpha(),
             };
@@ -291,12 +676,14 @@ impl<'a> Walker<'a> {
         for nid in &parent_node.children {
             let readguard = ui.map.get(nid).unwrap();
             let child_node = &readguard;
+            let rect = match child_node.element.get_rect() {
+                Some(vp) => vp.absolute(&parent_nodeinfo.rect),
+                None => parent_nodeinfo.rect.clone()
+            };
             let child_nodeinfo = NodeInfo {
                 handle: *nid,
-                rect: match child_node.element.get_rect() {
-                    Some(vp) => vp.absolute(&parent_nodeinfo.rect),
-                    None => parent_nodeinfo.rect.clone()
-                },
+                clip: rect.intersect(&parent_nodeinfo.clip),
+                rect: rect,
                 depth: parent_nodeinfo.depth+1,
                 alpha: parent_nodeinfo.alpha * child_node.element.get_alpha()
             };
@@ -2,7 +2,7 @@
 use super::Point;
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum TextColor {
     Black = 0,
@@ -15,8 +15,24 @@ pub enum TextColor {
     Lavender = 7
 }
 
+impl TextColor {
+    pub fn from_name(name: &str) -> Option<TextColor> {
+        match name {
+            "black" => Some(TextColor::Black),
+            "white" => Some(TextColor::White),
+            "red" => Some(TextColor::Red),
+            "gray" => Some(TextColor::Gray),
+            "gold" => Some(TextColor::Gold),
+            "green" => Some(TextColor::Green),
+            "blue" => Some(TextColor::Blue),
+            "lavender" => Some(TextColor::Lavender),
+            _ => None,
+        }
+    }
+}
+
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Font {
     Main = 0,
@@ -25,6 +41,18 @@ pub enum Font {
     Fantasy = 3,
 }
 
+impl Font {
+    pub fn from_name(name: &str) -> Option<Font> {
+        match name {
+            "main" => Some(Font::Main),
+            "mono" => Some(Font::Mono),
+            "title" => Some(Font::Title),
+            "fantasy" => Some(Font::Fantasy),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TextLine {
     pub ui_coordinates: Point,
@@ -35,3 +63,194 @@ pub struct TextLine {
     pub alpha: u8,
     pub text: String,
 }
+
+impl TextLine {
+    pub fn style(&self) -> TextStyle {
+        TextStyle {
+            color: self.color,
+            outline: self.outline,
+            font: self.font,
+        }
+    }
+}
+
+/// The per-glyph properties of a piece of text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextStyle {
+    pub color: TextColor,
+    pub outline: Option<TextColor>,
+    pub font: Font,
+}
+
+#[derive(Debug, Clone)]
+pub struct TextSpan {
+    pub style: TextStyle,
+    pub text: String,
+}
+
+/// A line of text made up of spans that each have their own style.
+/// Properties that apply to the line as a whole (position, height, alpha) are
+/// the same as for a TextLine.
+#[derive(Debug, Clone)]
+pub struct StyledLine {
+    pub ui_coordinates: Point,
+    pub lineheight: u8,
+    pub alpha: u8,
+    pub spans: Vec<TextSpan>,
+}
+
+/// The text of some spans, without any styling
+pub fn plain_text(spans: &[TextSpan]) -> String {
+    spans.iter().map(|s| &*s.text).collect()
+}
+
+/// Parse a line of markup into styled spans. Tags are enclosed in braces:
+///   {red}, {gold}, ...          set the text color
+///   {outline:blue}, {outline:none}  set or clear the outline color
+///   {font:mono}, {font:fantasy} set the font
+///   {/}                         go back to the base style
+///   {{                          a literal brace
+/// Anything in braces that is not a recognized tag is kept as text.
+pub fn parse_markup(markup: &str, base: TextStyle) -> Vec<TextSpan> {
+    let mut spans: Vec<TextSpan> = Vec::new();
+    let mut style = base;
+    let mut text = String::new();
+
+    let mut rest = markup;
+    while let Some(open) = rest.find('{') {
+        text.push_str(&rest[..open]);
+        rest = &rest[open..];
+
+        if rest.starts_with("{{") {
+            text.push('{');
+            rest = &rest[2..];
+            continue;
+        }
+
+        let close = match rest.find('}') {
+            Some(close) => close,
+            None => break,
+        };
+        let newstyle = match parse_tag(&rest[1..close], style, base) {
+            Some(newstyle) => newstyle,
+            None => {
+                // Not a tag, keep it as text
+                text.push('{');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        if newstyle != style {
+            if !text.is_empty() {
+                spans.push(TextSpan { style: style, text: text.clone() });
+                text.clear();
+            }
+            style = newstyle;
+        }
+        rest = &rest[close+1..];
+    }
+    text.push_str(rest);
+
+    if !text.is_empty() || spans.is_empty() {
+        spans.push(TextSpan { style: style, text: text });
+    }
+    spans
+}
+
+fn parse_tag(tag: &str, current: TextStyle, base: TextStyle) -> Option<TextStyle> {
+    let mut style = current;
+    if tag == "/" {
+        return Some(base);
+    } else if tag.starts_with("outline:") {
+        let name = &tag["outline:".len()..];
+        style.outline = if name == "none" {
+            None
+        } else {
+            Some(TextColor::from_name(name)?)
+        };
+    } else if tag.starts_with("font:") {
+        style.font = Font::from_name(&tag["font:".len()..])?;
+    } else {
+        style.color = TextColor::from_name(tag)?;
+    }
+    Some(style)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TextStyle, TextSpan, TextColor, Font, parse_markup, parse_tag, plain_text};
+
+    const BASE: TextStyle = TextStyle {
+        color: TextColor::White,
+        outline: None,
+        font: Font::Main,
+    };
+
+    fn colored(color: TextColor) -> TextStyle {
+        TextStyle { color: color, ..BASE }
+    }
+
+    fn styles(spans: &[TextSpan]) -> Vec<(TextStyle, &str)> {
+        spans.iter().map(|s| (s.style, &*s.text)).collect()
+    }
+
+    #[test]
+    fn test_parse_tag() {
+        let red = colored(TextColor::Red);
+        assert_eq!(parse_tag("red", BASE, BASE), Some(red));
+        assert_eq!(parse_tag("/", red, BASE), Some(BASE));
+        assert_eq!(parse_tag("outline:blue", red, BASE),
+                   Some(TextStyle { outline: Some(TextColor::Blue), ..red }));
+        assert_eq!(parse_tag("outline:none", red, BASE), Some(red));
+        assert_eq!(parse_tag("font:mono", red, BASE),
+                   Some(TextStyle { font: Font::Mono, ..red }));
+        assert_eq!(parse_tag("bogus", red, BASE), None);
+        assert_eq!(parse_tag("outline:bogus", red, BASE), None);
+        assert_eq!(parse_tag("font:bogus", red, BASE), None);
+    }
+
+    #[test]
+    fn test_parse_markup() {
+        let spans = parse_markup("plain", BASE);
+        assert_eq!(styles(&spans), vec![(BASE, "plain")]);
+
+        // Empty markup still gives a span
+        let spans = parse_markup("", BASE);
+        assert_eq!(styles(&spans), vec![(BASE, "")]);
+
+        let spans = parse_markup("a {red}b{/} c", BASE);
+        assert_eq!(styles(&spans), vec![(BASE, "a "), (colored(TextColor::Red), "b"),
+                                        (BASE, " c")]);
+    }
+
+    #[test]
+    fn test_parse_markup_braces() {
+        // A doubled brace is a literal one, and does not start a tag
+        let spans = parse_markup("{{red} {{", BASE);
+        assert_eq!(styles(&spans), vec![(BASE, "{red} {")]);
+
+        // Unknown tags are kept as text
+        let spans = parse_markup("{bogus}x{red}y", BASE);
+        assert_eq!(styles(&spans), vec![(BASE, "{bogus}x"), (colored(TextColor::Red), "y")]);
+
+        // So is a brace that is never closed
+        let spans = parse_markup("a {red", BASE);
+        assert_eq!(styles(&spans), vec![(BASE, "a {red")]);
+        let spans = parse_markup("{red}a {gold", BASE);
+        assert_eq!(styles(&spans), vec![(colored(TextColor::Red), "a {gold")]);
+    }
+
+    #[test]
+    fn test_parse_markup_nested() {
+        // Tags do not nest: {/} goes all the way back to the base style
+        let spans = parse_markup("{red}a{outline:black}b{/}c{/}d", BASE);
+        let red = colored(TextColor::Red);
+        let outlined = TextStyle { outline: Some(TextColor::Black), ..red };
+        assert_eq!(styles(&spans), vec![(red, "a"), (outlined, "b"), (BASE, "cd")]);
+        assert_eq!(plain_text(&spans), "abcd");
+
+        // A tag that changes nothing does not split the text
+        let spans = parse_markup("a{/}b{white}c", BASE);
+        assert_eq!(styles(&spans), vec![(BASE, "abc")]);
+    }
+}
@@ -1,5 +1,6 @@
 
 mod decorate;
+mod movable;
 
 use ui::Rect;
 
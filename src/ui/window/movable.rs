@@ -0,0 +1,240 @@
+
+use std::sync::{Arc, Mutex};
+use winit::{MouseButton, VirtualKeyCode as Key};
+use super::UiWindow;
+use ui::{Ui, Handle, Rect, AbsRect, UiEvent, EdgeOffsets,
+         WINDOW_TOP, WINDOW_BOTTOM, WINDOW_LEFT, WINDOW_RIGHT};
+
+// Windows cannot be resized smaller than this
+const MIN_WIDTH: f32 = 64.0;
+const MIN_HEIGHT: f32 = 48.0;
+
+// How close to the top edge grabs the edge rather than the title
+const TOP_GRIP: f32 = 3.0;
+
+// How far ctrl+arrow moves a focused window
+const NUDGE: f32 = 8.0;
+
+// Which edges a drag moves. Moving the window moves all of them.
+#[derive(Debug, Clone, Copy)]
+struct Grip {
+    left: bool,
+    top: bool,
+    right: bool,
+    bottom: bool,
+}
+
+impl Grip {
+    // What is under a point on the window, if anything draggable
+    fn at(rect: &AbsRect, x: f32, y: f32) -> Option<Grip> {
+        let grip = Grip {
+            left: x < rect.x + WINDOW_LEFT.width,
+            top: y < rect.y + TOP_GRIP,
+            right: x >= rect.x + rect.width - WINDOW_RIGHT.width,
+            bottom: y >= rect.y + rect.height - WINDOW_BOTTOM.height,
+        };
+        if grip.left || grip.top || grip.right || grip.bottom {
+            Some(grip)
+        } else if y < rect.y + WINDOW_TOP.height {
+            // the title area
+            Some(Grip { left: true, top: true, right: true, bottom: true })
+        } else {
+            None
+        }
+    }
+}
+
+// How far to move the near and far edges, given how far the cursor moved.
+// Windows stay on the screen and do not get too small.
+fn edge_deltas(near: bool, far: bool, delta: f32, start: f32, size: f32,
+               min_size: f32, screen_size: f32) -> (i32, i32)
+{
+    let end = start + size;
+    let delta = if near && far {
+        delta.max(-start).min(screen_size - end)
+    } else if near {
+        delta.max(-start).min(size - min_size)
+    } else if far {
+        delta.max(min_size - size).min(screen_size - end)
+    } else {
+        0.0
+    };
+    let delta = delta.round() as i32;
+    (if near { delta } else { 0 }, if far { delta } else { 0 })
+}
+
+// How far to move the near and far edges to bring a span within the screen
+fn fit_edges(start: f32, size: f32, screen_start: f32, screen_size: f32) -> (i32, i32) {
+    let shrink = (size - screen_size).max(0.0);
+    let size = size - shrink;
+    let target = start.max(screen_start).min(screen_start + screen_size - size);
+    let delta = target - start;
+    (delta.round() as i32, (delta - shrink).round() as i32)
+}
+
+struct Drag {
+    grip: Grip,
+    x: f32,
+    y: f32,
+    rect: AbsRect,
+    offsets: EdgeOffsets,
+}
+
+struct Movable {
+    name: String,
+    // Where the program put the window
+    rect: Rect,
+    // Where the user has moved it from there
+    offsets: EdgeOffsets,
+    drag: Option<Drag>,
+    // Whether the window has been moved by keys not yet released
+    nudged: bool,
+}
+
+impl Movable {
+    fn handle_event(&mut self, ui: &Ui, handle: Handle, event: &UiEvent) -> bool {
+        match *event {
+            UiEvent::MouseDown { button: MouseButton::Left, x, y } => {
+                let rect = match ui.absolute_rect(handle) {
+                    Some(rect) => rect,
+                    None => return false,
+                };
+                let grip = match Grip::at(&rect, x, y) {
+                    Some(grip) => grip,
+                    None => return false,
+                };
+                self.drag = Some(Drag {
+                    grip: grip,
+                    x: x,
+                    y: y,
+                    rect: rect,
+                    offsets: self.offsets,
+                });
+                true
+            },
+            UiEvent::MouseMove { x, y } => {
+                let offsets = match self.drag {
+                    Some(ref drag) => {
+                        let screen = ui.screen();
+                        let (left, right) = edge_deltas(
+                            drag.grip.left, drag.grip.right, x - drag.x,
+                            drag.rect.x, drag.rect.width, MIN_WIDTH, screen.width);
+                        let (top, bottom) = edge_deltas(
+                            drag.grip.top, drag.grip.bottom, y - drag.y,
+                            drag.rect.y, drag.rect.height, MIN_HEIGHT, screen.height);
+                        EdgeOffsets {
+                            left: drag.offsets.left + left,
+                            top: drag.offsets.top + top,
+                            right: drag.offsets.right + right,
+                            bottom: drag.offsets.bottom + bottom,
+                        }
+                    },
+                    None => return false,
+                };
+                if offsets != self.offsets {
+                    self.offsets = offsets;
+                    ui.set_rect(handle, self.moved_rect());
+                }
+                true
+            },
+            UiEvent::MouseUp { button: MouseButton::Left, .. } => {
+                if self.drag.take().is_none() {
+                    return false;
+                }
+                ui.save_offsets(&*self.name, self.offsets);
+                true
+            },
+            UiEvent::KeyDown { key, modifiers } if modifiers.ctrl => {
+                let (dx, dy) = match key {
+                    Key::Left => (-NUDGE, 0.0),
+                    Key::Right => (NUDGE, 0.0),
+                    Key::Up => (0.0, -NUDGE),
+                    Key::Down => (0.0, NUDGE),
+                    _ => return false,
+                };
+                let rect = match ui.absolute_rect(handle) {
+                    Some(rect) => rect,
+                    None => return false,
+                };
+                let screen = ui.screen();
+                let (left, right) = edge_deltas(true, true, dx, rect.x, rect.width,
+                                                MIN_WIDTH, screen.width);
+                let (top, bottom) = edge_deltas(true, true, dy, rect.y, rect.height,
+                                                MIN_HEIGHT, screen.height);
+                self.offsets.left += left;
+                self.offsets.right += right;
+                self.offsets.top += top;
+                self.offsets.bottom += bottom;
+                ui.set_rect(handle, self.moved_rect());
+                self.nudged = true;
+                true
+            },
+            UiEvent::ScreenResized { .. } => {
+                // Keep the window on the screen. Where the user put it stays
+                // saved, in case the screen gets bigger again.
+                let offsets = self.offsets;
+                self.fit_to_screen(&ui.screen());
+                if self.offsets != offsets {
+                    ui.set_rect(handle, self.moved_rect());
+                }
+                false
+            },
+            UiEvent::KeyUp { .. } if self.nudged => {
+                // Save once the keys are let go, rather than on every repeat
+                self.nudged = false;
+                ui.save_offsets(&*self.name, self.offsets);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn moved_rect(&self) -> Rect {
+        self.rect.with_edges_moved(self.offsets.left, self.offsets.top,
+                                   self.offsets.right, self.offsets.bottom)
+    }
+
+    // Move the offsets so that the window is on the screen, shrinking it if it
+    // is bigger than the screen (e.g. after the resolution has changed)
+    fn fit_to_screen(&mut self, screen: &AbsRect) {
+        let rect = self.moved_rect().absolute(screen);
+        let (left, right) = fit_edges(rect.x, rect.width, screen.x, screen.width);
+        let (top, bottom) = fit_edges(rect.y, rect.height, screen.y, screen.height);
+        self.offsets.left += left;
+        self.offsets.right += right;
+        self.offsets.top += top;
+        self.offsets.bottom += bottom;
+    }
+}
+
+impl UiWindow {
+    /// Let the user move a (root) window by dragging its title area, and
+    /// resize it by dragging its edges and corners. The window can also take
+    /// the keyboard focus, and then ctrl+arrow keys move it. Where they put
+    /// it is saved under the name, and restored here next time.
+    pub fn make_movable(ui: &Ui, handle: Handle, name: &str)
+    {
+        let rect = match ui.get_rect(handle) {
+            Some(rect) => rect,
+            None => return,
+        };
+        let mut movable = Movable {
+            name: name.to_owned(),
+            rect: rect,
+            offsets: ui.saved_offsets(name),
+            drag: None,
+            nudged: false,
+        };
+        if movable.offsets != EdgeOffsets::default() {
+            // The screen may be smaller than when the window was put there
+            movable.fit_to_screen(&ui.screen());
+            ui.set_rect(handle, movable.moved_rect());
+        }
+
+        let movable = Mutex::new(movable);
+        ui.add_listener(handle, Arc::new(move |ui: &Ui, handle: Handle, event: &UiEvent| {
+            movable.lock().unwrap().handle_event(ui, handle, event)
+        }));
+        ui.set_focusable(handle, true);
+    }
+}